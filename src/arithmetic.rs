@@ -0,0 +1,540 @@
+use crate::error::ShellError;
+
+// Integer arithmetic as used by `$(( ))`, `(( ))` and `let`. Variables live in
+// the process environment, the same place `cd` keeps OLDPWD.
+
+const MAX_RECURSION: usize = 64;
+
+pub fn evaluate(expr: &str) -> Result<i64, ShellError> {
+    evaluate_nested(expr, 0)
+}
+
+fn evaluate_nested(expr: &str, depth: usize) -> Result<i64, ShellError> {
+    if depth > MAX_RECURSION {
+        return Err(ShellError::ArithmeticError(
+            format!("{}: expression recursion level exceeded", expr)
+        ));
+    }
+    if expr.trim().is_empty() {
+        return Ok(0);
+    }
+
+    let tokens = tokenize(expr)?;
+    let mut parser = Parser { tokens: &tokens, pos: 0, source: expr };
+    let ast = parser.parse_comma()?;
+    if let Some(token) = parser.peek() {
+        return Err(ShellError::ArithmeticError(
+            format!("{}: syntax error: invalid arithmetic operator (error token is \"{}\")", expr, token)
+        ));
+    }
+
+    let mut evaluator = Evaluator { depth, source: expr };
+    evaluator.eval(&ast)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Op(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::Op(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+// Longest operators first so that `<<=` wins over `<<` and `<`.
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+    "+=", "-=", "*=", "/=", "%=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<",
+    ">", "&", "^", "|", "!", "~", "?", ":", "=", "(", ")", ",",
+];
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, ShellError> {
+    let chars: Vec<char> = expr.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let ch = chars[i];
+        if ch.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        if ch.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || matches!(chars[i], '#' | '@' | '_')) {
+                i += 1;
+            }
+            let literal: String = chars[start..i].iter().collect();
+            tokens.push(Token::Number(parse_number(&literal, expr)?));
+            continue;
+        }
+
+        // `$name` inside an expression is the same as `name`.
+        let ident_start = if ch == '$' && chars.get(i + 1).is_some_and(|c| c.is_ascii_alphabetic() || *c == '_') {
+            i + 1
+        } else {
+            i
+        };
+        if chars[ident_start].is_ascii_alphabetic() || chars[ident_start] == '_' {
+            i = ident_start;
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+            continue;
+        }
+
+        let rest: String = chars[i..].iter().take(3).collect();
+        match OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            Some(op) => {
+                tokens.push(Token::Op(op));
+                i += op.chars().count();
+            }
+            None => {
+                let token: String = chars[i..].iter().collect();
+                return Err(ShellError::ArithmeticError(
+                    format!("{}: syntax error: operand expected (error token is \"{}\")", expr, token.trim())
+                ));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Parses an integer constant: decimal, `0x` hex, leading-zero octal or
+/// `base#digits` with a base between 2 and 64.
+fn parse_number(literal: &str, expr: &str) -> Result<i64, ShellError> {
+    let invalid = || ShellError::ArithmeticError(
+        format!("{}: value too great for base (error token is \"{}\")", expr, literal)
+    );
+
+    let (base, digits) = if let Some((base, digits)) = literal.split_once('#') {
+        let base: u32 = base.parse().map_err(|_| invalid())?;
+        if !(2..=64).contains(&base) {
+            return Err(ShellError::ArithmeticError(
+                format!("{}: invalid arithmetic base (error token is \"{}\")", expr, literal)
+            ));
+        }
+        (base, digits)
+    } else if let Some(hex) = literal.strip_prefix("0x").or_else(|| literal.strip_prefix("0X")) {
+        (16, hex)
+    } else if literal.len() > 1 && literal.starts_with('0') {
+        (8, &literal[1..])
+    } else {
+        (10, literal)
+    };
+
+    if digits.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut value: i64 = 0;
+    for c in digits.chars() {
+        let digit = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 10,
+            'A'..='Z' if base <= 36 => c as u32 - 'A' as u32 + 10,
+            'A'..='Z' => c as u32 - 'A' as u32 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return Err(invalid()),
+        };
+        if digit >= base {
+            return Err(invalid());
+        }
+        value = value.wrapping_mul(base as i64).wrapping_add(digit as i64);
+    }
+    Ok(value)
+}
+
+#[derive(Debug)]
+enum Expr {
+    Number(i64),
+    Variable(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// `name op= value`, where a plain `=` has no operator.
+    Assign(String, Option<&'static str>, Box<Expr>),
+    /// `++name`, `name--`, ...
+    Step { name: String, delta: i64, prefix: bool },
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    source: &'a str,
+}
+
+// Binary operator levels from loosest to tightest binding. `**` and the
+// assignment operators are right-associative and handled separately.
+const BINARY_LEVELS: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<=", ">=", "<", ">"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), ShellError> {
+        if self.peek_op() == Some(op) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.syntax_error(&format!("'{}' expected", op)))
+        }
+    }
+
+    fn syntax_error(&self, what: &str) -> ShellError {
+        let token = self.peek().map(|t| t.to_string()).unwrap_or_default();
+        ShellError::ArithmeticError(
+            format!("{}: syntax error: {} (error token is \"{}\")", self.source, what, token)
+        )
+    }
+
+    fn parse_comma(&mut self) -> Result<Expr, ShellError> {
+        let mut left = self.parse_assignment()?;
+        while self.peek_op() == Some(",") {
+            self.pos += 1;
+            let right = self.parse_assignment()?;
+            left = Expr::Binary(",", Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_assignment(&mut self) -> Result<Expr, ShellError> {
+        if let Some(Token::Ident(name)) = self.peek() {
+            let name = name.clone();
+            if let Some(Token::Op(op)) = self.tokens.get(self.pos + 1) {
+                let compound = match *op {
+                    "=" => Some(None),
+                    "+=" => Some(Some("+")),
+                    "-=" => Some(Some("-")),
+                    "*=" => Some(Some("*")),
+                    "/=" => Some(Some("/")),
+                    "%=" => Some(Some("%")),
+                    "<<=" => Some(Some("<<")),
+                    ">>=" => Some(Some(">>")),
+                    "&=" => Some(Some("&")),
+                    "^=" => Some(Some("^")),
+                    "|=" => Some(Some("|")),
+                    _ => None,
+                };
+                if let Some(op) = compound {
+                    self.pos += 2;
+                    let value = self.parse_assignment()?;
+                    return Ok(Expr::Assign(name, op, Box::new(value)));
+                }
+            }
+        }
+        self.parse_ternary()
+    }
+
+    fn parse_ternary(&mut self) -> Result<Expr, ShellError> {
+        let condition = self.parse_binary(0)?;
+        if self.peek_op() != Some("?") {
+            return Ok(condition);
+        }
+        self.pos += 1;
+        let then = self.parse_comma()?;
+        self.expect(":")?;
+        let otherwise = self.parse_assignment()?;
+        Ok(Expr::Ternary(Box::new(condition), Box::new(then), Box::new(otherwise)))
+    }
+
+    fn parse_binary(&mut self, level: usize) -> Result<Expr, ShellError> {
+        if level == BINARY_LEVELS.len() {
+            return self.parse_power();
+        }
+        let mut left = self.parse_binary(level + 1)?;
+        while let Some(op) = self.peek_op() {
+            if !BINARY_LEVELS[level].contains(&op) {
+                break;
+            }
+            self.pos += 1;
+            let right = self.parse_binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_power(&mut self) -> Result<Expr, ShellError> {
+        let base = self.parse_unary()?;
+        if self.peek_op() == Some("**") {
+            self.pos += 1;
+            let exponent = self.parse_power()?;
+            return Ok(Expr::Binary("**", Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ShellError> {
+        match self.peek_op() {
+            Some(op @ ("++" | "--")) => {
+                self.pos += 1;
+                match self.peek() {
+                    Some(Token::Ident(name)) => {
+                        let name = name.clone();
+                        self.pos += 1;
+                        let delta = if op == "++" { 1 } else { -1 };
+                        Ok(Expr::Step { name, delta, prefix: true })
+                    }
+                    // `--5` is just two negations.
+                    _ => {
+                        let operand = self.parse_unary()?;
+                        let sign = if op == "++" { "+" } else { "-" };
+                        Ok(Expr::Unary(sign, Box::new(Expr::Unary(sign, Box::new(operand)))))
+                    }
+                }
+            }
+            Some(op @ ("+" | "-" | "!" | "~")) => {
+                self.pos += 1;
+                let operand = self.parse_unary()?;
+                Ok(Expr::Unary(op, Box::new(operand)))
+            }
+            _ => self.parse_postfix(),
+        }
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr, ShellError> {
+        match self.peek().cloned() {
+            Some(Token::Number(n)) => {
+                self.pos += 1;
+                Ok(Expr::Number(n))
+            }
+            Some(Token::Ident(name)) => {
+                self.pos += 1;
+                if let Some(op @ ("++" | "--")) = self.peek_op() {
+                    self.pos += 1;
+                    let delta = if op == "++" { 1 } else { -1 };
+                    return Ok(Expr::Step { name, delta, prefix: false });
+                }
+                Ok(Expr::Variable(name))
+            }
+            Some(Token::Op("(")) => {
+                self.pos += 1;
+                let inner = self.parse_comma()?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            _ => Err(self.syntax_error("operand expected")),
+        }
+    }
+}
+
+struct Evaluator<'a> {
+    depth: usize,
+    source: &'a str,
+}
+
+impl Evaluator<'_> {
+    fn eval(&mut self, expr: &Expr) -> Result<i64, ShellError> {
+        match expr {
+            Expr::Number(n) => Ok(*n),
+            Expr::Variable(name) => self.lookup(name),
+            Expr::Unary(op, operand) => {
+                let value = self.eval(operand)?;
+                Ok(match *op {
+                    "-" => value.wrapping_neg(),
+                    "!" => (value == 0) as i64,
+                    "~" => !value,
+                    _ => value,
+                })
+            }
+            Expr::Binary("&&", left, right) => {
+                Ok((self.eval(left)? != 0 && self.eval(right)? != 0) as i64)
+            }
+            Expr::Binary("||", left, right) => {
+                Ok((self.eval(left)? != 0 || self.eval(right)? != 0) as i64)
+            }
+            Expr::Binary(op, left, right) => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                self.apply(op, left, right)
+            }
+            Expr::Ternary(condition, then, otherwise) => {
+                if self.eval(condition)? != 0 {
+                    self.eval(then)
+                } else {
+                    self.eval(otherwise)
+                }
+            }
+            Expr::Assign(name, op, value) => {
+                let value = self.eval(value)?;
+                let value = match op {
+                    Some(op) => {
+                        let current = self.lookup(name)?;
+                        self.apply(op, current, value)?
+                    }
+                    None => value,
+                };
+                set_variable(name, value);
+                Ok(value)
+            }
+            Expr::Step { name, delta, prefix } => {
+                let old = self.lookup(name)?;
+                let new = old.wrapping_add(*delta);
+                set_variable(name, new);
+                Ok(if *prefix { new } else { old })
+            }
+        }
+    }
+
+    fn apply(&self, op: &str, left: i64, right: i64) -> Result<i64, ShellError> {
+        Ok(match op {
+            "," => right,
+            "+" => left.wrapping_add(right),
+            "-" => left.wrapping_sub(right),
+            "*" => left.wrapping_mul(right),
+            "/" | "%" => {
+                if right == 0 {
+                    return Err(ShellError::ArithmeticError(
+                        format!("{}: division by 0", self.source)
+                    ));
+                }
+                if op == "/" { left.wrapping_div(right) } else { left.wrapping_rem(right) }
+            }
+            "**" => {
+                if right < 0 {
+                    return Err(ShellError::ArithmeticError(
+                        format!("{}: exponent less than 0", self.source)
+                    ));
+                }
+                let (mut result, mut base, mut exponent) = (1i64, left, right);
+                while exponent > 0 {
+                    if exponent & 1 == 1 {
+                        result = result.wrapping_mul(base);
+                    }
+                    base = base.wrapping_mul(base);
+                    exponent >>= 1;
+                }
+                result
+            }
+            "<<" => left.wrapping_shl(right as u32),
+            ">>" => left.wrapping_shr(right as u32),
+            "<" => (left < right) as i64,
+            ">" => (left > right) as i64,
+            "<=" => (left <= right) as i64,
+            ">=" => (left >= right) as i64,
+            "==" => (left == right) as i64,
+            "!=" => (left != right) as i64,
+            "&" => left & right,
+            "^" => left ^ right,
+            "|" => left | right,
+            _ => unreachable!("unknown arithmetic operator {}", op),
+        })
+    }
+
+    /// Unset and empty variables count as 0; a value that is not a number is
+    /// itself evaluated as an expression, as other shells do.
+    fn lookup(&self, name: &str) -> Result<i64, ShellError> {
+        let value = std::env::var(name).unwrap_or_default();
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(0);
+        }
+        if let Ok(n) = value.parse::<i64>() {
+            return Ok(n);
+        }
+        evaluate_nested(value, self.depth + 1)
+    }
+}
+
+fn set_variable(name: &str, value: i64) {
+    unsafe {
+        std::env::set_var(name, value.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(expr: &str) -> String {
+        match evaluate(expr) {
+            Err(ShellError::ArithmeticError(message)) => message,
+            other => panic!("{}: expected an arithmetic error, got {:?}", expr, other),
+        }
+    }
+
+    #[test]
+    fn precedence_and_grouping() {
+        assert_eq!(evaluate("2 + 3 * 4").unwrap(), 14);
+        assert_eq!(evaluate("(2 + 3) * 4").unwrap(), 20);
+        assert_eq!(evaluate("10 - 4 - 3").unwrap(), 3);
+        assert_eq!(evaluate("1 + 2 == 3 && 4 > 3").unwrap(), 1);
+        assert_eq!(evaluate("1 | 2 ^ 3 & 4").unwrap(), 3);
+        assert_eq!(evaluate("-2 * -3").unwrap(), 6);
+        assert_eq!(evaluate("1 ? 2 : 3").unwrap(), 2);
+    }
+
+    #[test]
+    fn power_is_right_associative() {
+        assert_eq!(evaluate("2 ** 10").unwrap(), 1024);
+        assert_eq!(evaluate("2 ** 3 ** 2").unwrap(), 512);
+        assert_eq!(evaluate("3 * 2 ** 2").unwrap(), 12);
+        assert_eq!(evaluate("5 ** 0").unwrap(), 1);
+        assert!(error("2 ** -1").contains("exponent less than 0"));
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        assert_eq!(evaluate("0 && 1 / 0").unwrap(), 0);
+        assert_eq!(evaluate("1 || 1 / 0").unwrap(), 1);
+        assert_eq!(evaluate("0 && (ARITH_TEST_SKIPPED = 5)").unwrap(), 0);
+        assert!(std::env::var("ARITH_TEST_SKIPPED").is_err());
+        assert_eq!(evaluate("1 && (ARITH_TEST_SET = 5)").unwrap(), 1);
+        assert_eq!(std::env::var("ARITH_TEST_SET").unwrap(), "5");
+    }
+
+    #[test]
+    fn division_by_zero_is_an_error() {
+        assert!(error("1 / 0").contains("division by 0"));
+        assert!(error("7 % (2 - 2)").contains("division by 0"));
+    }
+
+    #[test]
+    fn overflowing_division_wraps() {
+        assert_eq!(evaluate("-9223372036854775807 - 1").unwrap(), i64::MIN);
+        assert_eq!(evaluate("(-9223372036854775807 - 1) / -1").unwrap(), i64::MIN);
+        assert_eq!(evaluate("(-9223372036854775807 - 1) % -1").unwrap(), 0);
+    }
+
+    #[test]
+    fn number_bases() {
+        assert_eq!(evaluate("010").unwrap(), 8);
+        assert_eq!(evaluate("0x1f").unwrap(), 31);
+        assert_eq!(evaluate("2#101").unwrap(), 5);
+        assert!(error("08").contains("value too great for base"));
+        assert!(error("2#102").contains("value too great for base"));
+        assert!(error("65#1").contains("invalid arithmetic base"));
+    }
+}
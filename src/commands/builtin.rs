@@ -1,19 +1,34 @@
-use crate::commands::CommandExecutor;
+use crate::arithmetic;
+use crate::commands::{ text_args, CommandExecutor };
 use crate::error::ShellError;
+use crate::shell;
 use crate::commands::filesystem::*;
+use crate::commands::conditional::*;
+use crate::commands::colors::DircolorsCommand;
+use std::ffi::{ OsStr, OsString };
+use std::io::{ self, Write };
+use std::os::unix::ffi::OsStrExt;
 
 pub struct EchoCommand;
 pub struct ExitCommand;
+pub struct ExportCommand;
 pub struct HelpCommand;
+pub struct LetCommand;
 
 impl CommandExecutor for EchoCommand {
-    fn execute(&self, args: &[String]) -> Result<(), ShellError> {
-        if args.is_empty() {
-            println!();
-        } else {
-            println!("{}", args.join(" "));
+    fn execute(&self, args: &[OsString]) -> Result<(), ShellError> {
+        // Written as bytes so that names which are not UTF-8 come out as
+        // they went in.
+        let mut line = Vec::new();
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                line.push(b' ');
+            }
+            line.extend_from_slice(arg.as_bytes());
         }
-        Ok(())
+        line.push(b'\n');
+        let mut out = io::stdout().lock();
+        out.write_all(&line).and_then(|_| out.flush()).map_err(ShellError::IoError)
     }
 
     fn help(&self) -> &str {
@@ -22,24 +37,92 @@ impl CommandExecutor for EchoCommand {
 } 
 
 impl CommandExecutor for ExitCommand {
-    fn execute(&self, _args: &[String]) -> Result<(), ShellError> {
-        std::process::exit(0);
+    fn execute(&self, args: &[OsString]) -> Result<(), ShellError> {
+        match text_args(args).as_slice() {
+            [] => Err(ShellError::Exit(None)),
+            [code] => match code.parse::<i32>() {
+                Ok(code) => Err(ShellError::Exit(Some(code & 0xff))),
+                Err(_) => {
+                    eprintln!("exit: {}: numeric argument required", code);
+                    Err(ShellError::Exit(Some(2)))
+                }
+            },
+            _ => Err(ShellError::ExecutionError("exit: too many arguments".to_string())),
+        }
+    }
+
+    fn help(&self) -> &str {
+        "exit [n] - Exit the shell with status n (default: the last status)"
+    }
+}
+
+impl CommandExecutor for ExportCommand {
+    fn execute(&self, args: &[OsString]) -> Result<(), ShellError> {
+        // Variables live in the environment already, so every one is
+        // exported; `export NAME` has nothing left to do.
+        let mut failed = false;
+        for arg in args {
+            let bytes = arg.as_bytes();
+            let (name, value) = match bytes.iter().position(|&b| b == b'=') {
+                Some(i) => (&bytes[..i], Some(OsStr::from_bytes(&bytes[i + 1..]))),
+                None => (bytes, None),
+            };
+            let valid = name.first().is_some_and(|b| b.is_ascii_alphabetic() || *b == b'_')
+                && name.iter().all(|b| b.is_ascii_alphanumeric() || *b == b'_');
+            if !valid {
+                eprintln!("export: `{}': not a valid identifier", arg.to_string_lossy());
+                failed = true;
+                continue;
+            }
+            if let Some(value) = value {
+                unsafe {
+                    std::env::set_var(OsStr::from_bytes(name), value);
+                }
+            }
+        }
+        if failed {
+            return Err(ShellError::ExitStatus(1));
+        }
+        Ok(())
     }
 
     fn help(&self) -> &str {
-        "exit - Exit the shell"
+        "export [name[=value]...] - Set variables; all variables are passed on to commands"
     }
 }
 
+impl CommandExecutor for LetCommand {
+    fn execute(&self, args: &[OsString]) -> Result<(), ShellError> {
+        let args = text_args(args);
+        if args.is_empty() {
+            return Err(ShellError::ExecutionError("let: expression expected".to_string()));
+        }
+
+        let mut last = 0;
+        for expr in &args {
+            last = arithmetic::evaluate(expr)?;
+        }
+
+        if last == 0 {
+            return Err(ShellError::ExitStatus(1));
+        }
+        Ok(())
+    }
+
+    fn help(&self) -> &str {
+        "let expression... - Evaluate arithmetic expressions; fails if the last one is 0"
+    }
+}
 
 impl CommandExecutor for HelpCommand {
-    fn execute(&self, args: &[String]) -> Result<(), ShellError> {
+    fn execute(&self, args: &[OsString]) -> Result<(), ShellError> {
+        let args = text_args(args);
         if args.is_empty() || args.len() > 1 {
             println!(
                 "Usage: help [command]\n\
                  Only one command is allowed\n\
                  Available commands:\n\
-                 echo, exit, help, pwd, cd, ls, cat, mkdir, cp, mv, rm"
+                 echo, exit, export, help, let, set, trap, test, [, pwd, cd, ls, cat, mkdir, cp, mv, rm, getfacl, dircolors"
             );
             return Ok(());
         }
@@ -48,7 +131,11 @@ impl CommandExecutor for HelpCommand {
         match cmd.as_str() {
             "echo"  => println!("{}", EchoCommand.help()),
             "exit"  => println!("{}", ExitCommand.help()),
+            "export" => println!("{}", ExportCommand.help()),
             "help"  => println!("{}", HelpCommand.help()),
+            "let"   => println!("{}", LetCommand.help()),
+            "test"  => println!("{}", TestCommand.help()),
+            "["     => println!("{}", BracketCommand.help()),
             "pwd"   => println!("{}", PwdCommand.help()),
             "cd"    => println!("{}", CdCommand.help()),
             "ls"    => println!("{}", LsCommand.help()),
@@ -57,7 +144,12 @@ impl CommandExecutor for HelpCommand {
             "cp"    => println!("{}", CpCommand.help()),
             "mv"    => println!("{}", MvCommand.help()),
             "rm"    => println!("{}", RmCommand.help()),
-            _ => println!("Unknown command: {}", cmd),
+            "getfacl" => println!("{}", GetfaclCommand.help()),
+            "dircolors" => println!("{}", DircolorsCommand.help()),
+            _ => match shell::shell_builtin(cmd) {
+                Some(builtin) => println!("{}", builtin.help()),
+                None => println!("Unknown command: {}", cmd),
+            },
         }
 
         Ok(())
@@ -0,0 +1,137 @@
+use crate::encoding;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+
+/// How `ls` orders names, picked from the locale like other tools do so
+/// that listings compare cleanly in diffs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Collation {
+    /// The C and POSIX locales: plain byte order, so `B` < `a` < `é`.
+    Bytes,
+    /// Any other locale: letters and digits first, ignoring case and
+    /// accents, then accents, then case (lower first), then punctuation.
+    Unicode,
+}
+
+impl Collation {
+    /// `LC_ALL`, then `LC_COLLATE`, then `LANG`; an unset or empty locale
+    /// is the POSIX one.
+    pub fn from_env() -> Self {
+        let locale = ["LC_ALL", "LC_COLLATE", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .unwrap_or_default();
+        // C.UTF-8 collates by code point, which for UTF-8 is byte order.
+        let posix = locale.is_empty()
+            || locale == "POSIX"
+            || locale == "C"
+            || locale.starts_with("C.")
+            || locale.starts_with("POSIX.");
+        if posix { Collation::Bytes } else { Collation::Unicode }
+    }
+
+    /// A key that orders like the collation. Keys only compare equal for
+    /// identical names, since the raw bytes are the last tiebreak.
+    pub fn key(&self, name: &OsStr) -> CollationKey {
+        let bytes = name.as_bytes().to_vec();
+        if *self == Collation::Bytes {
+            return CollationKey { bytes, ..CollationKey::default() };
+        }
+
+        let mut key = CollationKey::default();
+        for (position, c) in encoding::decode_os(name).chars().enumerate() {
+            if c.is_alphanumeric() {
+                let lower = c.to_lowercase().next().unwrap_or(c);
+                let base = fold(lower);
+                // Digits sort before letters.
+                key.primary.push(if c.is_numeric() { base as u32 } else { 0x110000 + base as u32 });
+                key.accents.push(if base == lower { 0 } else { lower as u32 });
+                key.case.push(c.is_uppercase());
+            } else {
+                key.punctuation.push((position, c));
+            }
+        }
+        key.bytes = bytes;
+        key
+    }
+}
+
+/// Sort key for one name, compared field by field.
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CollationKey {
+    primary: Vec<u32>,
+    accents: Vec<u32>,
+    case: Vec<bool>,
+    /// Where the characters skipped above were: a name without any comes
+    /// first, so `ab` < `a-b` < `a_b`.
+    punctuation: Vec<(usize, char)>,
+    bytes: Vec<u8>,
+}
+
+/// Base letters for U+00C0 to U+017F, already lowercased.
+const LATIN_BASE: &str = "aaaaaaaceeeeiiiidnooooo×ouuuuyþsaaaaaaaceeeeiiiidnooooo÷ouuuuyþy\
+aaaaaaccccccccddddeeeeeeeeeegggggggghhhhiiiiiiiiiiiijjkkklllllll\
+lllnnnnnnnnnoooooooorrrrrrssssssssttttttuuuuuuuuuuuuwwyyyzzzzzzs";
+
+/// The unaccented form of a lowercase Latin letter, or the letter itself.
+fn fold(c: char) -> char {
+    match (c as u32).checked_sub(0xc0) {
+        Some(offset) => LATIN_BASE.chars().nth(offset as usize).unwrap_or(c),
+        None => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(collation: Collation, names: &[&str]) -> Vec<String> {
+        let mut names: Vec<&str> = names.to_vec();
+        names.sort_by_key(|name| collation.key(OsStr::new(name)));
+        names.into_iter().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn bytes_order_is_plain_byte_order() {
+        assert_eq!(
+            sorted(Collation::Bytes, &["b", "é", "a", "_x", "B", "10", "9"]),
+            ["10", "9", "B", "_x", "a", "b", "é"]
+        );
+    }
+
+    #[test]
+    fn unicode_order_ignores_case_and_accents_first() {
+        assert_eq!(
+            sorted(Collation::Unicode, &["f", "é", "B", "e", "a", "E", "b"]),
+            ["a", "b", "B", "e", "E", "é", "f"]
+        );
+        assert_eq!(
+            sorted(Collation::Unicode, &["zeta", "Ångström", "apple", "angstrom", "Zürich"]),
+            ["angstrom", "Ångström", "apple", "zeta", "Zürich"]
+        );
+    }
+
+    #[test]
+    fn accented_latin_letters_sort_with_their_base_letter() {
+        assert_eq!(
+            sorted(Collation::Unicode, &["ñu", "nz", "na", "ça", "cb", "ÿ", "y", "z"]),
+            ["ça", "cb", "na", "ñu", "nz", "y", "ÿ", "z"]
+        );
+    }
+
+    #[test]
+    fn digits_before_letters_and_punctuation_last() {
+        assert_eq!(sorted(Collation::Unicode, &["b", "2", "A", "1"]), ["1", "2", "A", "b"]);
+        assert_eq!(sorted(Collation::Unicode, &["a_b", "a-b", "ab", "ac"]), ["ab", "a-b", "a_b", "ac"]);
+        assert_eq!(sorted(Collation::Unicode, &[".profile", "notes", ".bashrc"]), [".bashrc", "notes", ".profile"]);
+    }
+
+    #[test]
+    fn only_identical_names_have_equal_keys() {
+        let key = |name: &str| Collation::Unicode.key(OsStr::new(name));
+        assert_eq!(key("Same"), key("Same"));
+        assert_ne!(key("a"), key("A"));
+        assert_ne!(key("e"), key("é"));
+    }
+}
@@ -0,0 +1,182 @@
+use crate::commands::predicates;
+use crate::commands::{ text_args, CommandExecutor };
+use crate::error::ShellError;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::os::unix::fs::{ FileTypeExt, MetadataExt };
+use std::path::Path;
+
+pub struct DircolorsCommand;
+
+/// Colours used when `LS_COLORS` is not set, in the same format.
+pub const DEFAULT_LS_COLORS: &str = "rs=0:di=01;34:ln=01;36:mh=00:pi=40;33:so=01;35:do=01;35:\
+bd=40;33;01:cd=40;33;01:or=40;31;01:mi=00:su=37;41:sg=30;43:ca=00:tw=30;42:ow=34;42:st=37;44:\
+ex=01;32:*.tar=01;31:*.tgz=01;31:*.zip=01;31:*.gz=01;31:*.bz2=01;31:*.xz=01;31:*.zst=01;31:\
+*.7z=01;31:*.rar=01;31:*.deb=01;31:*.rpm=01;31:*.jar=01;31:*.jpg=01;35:*.jpeg=01;35:\
+*.png=01;35:*.gif=01;35:*.bmp=01;35:*.svg=01;35:*.webp=01;35:*.mp4=01;35:*.mkv=01;35:\
+*.webm=01;35:*.avi=01;35:*.mov=01;35:*.mp3=00;36:*.flac=00;36:*.ogg=00;36:*.wav=00;36:\
+*.m4a=00;36";
+
+/// What each two-letter key in `LS_COLORS` colours, for `dircolors -p`.
+const KEY_DESCRIPTIONS: &[(&str, &str)] = &[
+    ("rs", "reset to no colour"),
+    ("no", "anything without a colour of its own"),
+    ("fi", "regular file"),
+    ("di", "directory"),
+    ("ln", "symbolic link"),
+    ("mh", "regular file with more than one link"),
+    ("pi", "named pipe"),
+    ("so", "socket"),
+    ("do", "door"),
+    ("bd", "block device"),
+    ("cd", "character device"),
+    ("or", "symlink to a missing file"),
+    ("mi", "missing file a symlink points to"),
+    ("su", "setuid file"),
+    ("sg", "setgid file"),
+    ("ca", "file with capabilities"),
+    ("tw", "sticky and other-writable directory"),
+    ("ow", "other-writable directory"),
+    ("st", "sticky directory"),
+    ("ex", "executable file"),
+];
+
+/// A parsed `LS_COLORS`: SGR sequences keyed by file type, plus `*suffix`
+/// patterns for regular files.
+#[derive(Debug, Clone)]
+pub struct ColorTable {
+    types: HashMap<String, String>,
+    suffixes: Vec<(String, String)>,
+}
+
+impl ColorTable {
+    pub fn from_env() -> Self {
+        match std::env::var("LS_COLORS") {
+            Ok(spec) if !spec.is_empty() => Self::parse(&spec),
+            _ => Self::parse(DEFAULT_LS_COLORS),
+        }
+    }
+
+    /// Reads `key=value` pairs separated by `:`. Malformed entries are
+    /// skipped, as GNU ls does after warning.
+    pub fn parse(spec: &str) -> Self {
+        let mut table = ColorTable { types: HashMap::new(), suffixes: Vec::new() };
+        for entry in spec.split(':') {
+            let Some((key, value)) = entry.split_once('=') else {
+                continue;
+            };
+            if !value.chars().all(|c| c.is_ascii_digit() || c == ';') {
+                continue;
+            }
+            if let Some(suffix) = key.strip_prefix('*') {
+                table.suffixes.retain(|(existing, _)| !existing.eq_ignore_ascii_case(suffix));
+                table.suffixes.push((suffix.to_lowercase(), value.to_string()));
+            } else {
+                table.types.insert(key.to_string(), value.to_string());
+            }
+        }
+        table
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.types.get(key).map(String::as_str).filter(|value| !is_reset(value))
+    }
+
+    /// The SGR sequence for a file, or `None` to leave it uncoloured.
+    /// `metadata` is from lstat; `None` means the file does not exist.
+    pub fn style(&self, name: &str, path: &Path, metadata: Option<&fs::Metadata>) -> Option<&str> {
+        let Some(metadata) = metadata else {
+            return self.get("mi").or_else(|| self.get("or"));
+        };
+        let ftype = metadata.file_type();
+
+        let key = if ftype.is_symlink() {
+            if fs::metadata(path).is_err() && self.get("or").is_some() { "or" } else { "ln" }
+        } else if ftype.is_dir() {
+            let sticky = predicates::is_sticky(metadata);
+            let other_writable = predicates::is_other_writable(metadata);
+            match (sticky, other_writable) {
+                (true, true) if self.get("tw").is_some() => "tw",
+                (false, true) if self.get("ow").is_some() => "ow",
+                (true, false) if self.get("st").is_some() => "st",
+                _ => "di",
+            }
+        } else if ftype.is_fifo() {
+            "pi"
+        } else if ftype.is_socket() {
+            "so"
+        } else if ftype.is_block_device() {
+            "bd"
+        } else if ftype.is_char_device() {
+            "cd"
+        } else if predicates::is_setuid(metadata) && self.get("su").is_some() {
+            "su"
+        } else if predicates::is_setgid(metadata) && self.get("sg").is_some() {
+            "sg"
+        } else if predicates::is_executable(metadata) && self.get("ex").is_some() {
+            "ex"
+        } else if metadata.nlink() > 1 && self.get("mh").is_some() {
+            "mh"
+        } else {
+            let lower = name.to_lowercase();
+            // Later entries override earlier ones, as when LS_COLORS is
+            // built up by appending.
+            if let Some((_, value)) = self.suffixes.iter().rev().find(|(suffix, _)| lower.ends_with(suffix.as_str())) {
+                return Some(value.as_str()).filter(|value| !is_reset(value));
+            }
+            "fi"
+        };
+        self.get(key).or_else(|| self.get("no"))
+    }
+
+    /// Colours `text`, the name as it is to be shown, by the type of the
+    /// file at `path` or by the suffix of `name`.
+    pub fn paint(&self, name: &str, text: &str, path: &Path, metadata: Option<&fs::Metadata>) -> String {
+        match self.style(name, path, metadata) {
+            Some(style) => format!("\x1b[{}m{}\x1b[0m", style, text),
+            None => text.to_string(),
+        }
+    }
+}
+
+fn is_reset(value: &str) -> bool {
+    value.is_empty() || value.chars().all(|c| c == '0')
+}
+
+impl CommandExecutor for DircolorsCommand {
+    fn execute(&self, args: &[OsString]) -> Result<(), ShellError> {
+        let args = text_args(args);
+        if args.len() > 1 {
+            return Err(ShellError::ExecutionError("dircolors: too many arguments".to_string()));
+        }
+        match args.first().map(String::as_str) {
+            None | Some("-b") | Some("--sh") | Some("--bourne-shell") => {
+                println!("LS_COLORS='{}';", DEFAULT_LS_COLORS);
+                println!("export LS_COLORS");
+            }
+            Some("-c") | Some("--csh") | Some("--c-shell") => {
+                println!("setenv LS_COLORS '{}'", DEFAULT_LS_COLORS);
+            }
+            Some("-p") | Some("--print-database") => {
+                let table = ColorTable::parse(DEFAULT_LS_COLORS);
+                for (key, description) in KEY_DESCRIPTIONS {
+                    if let Some(value) = table.types.get(*key) {
+                        println!("{:<8}{:<12}# {}", key, value, description);
+                    }
+                }
+                for (suffix, value) in &table.suffixes {
+                    println!("{:<8}{}", format!("*{}", suffix), value);
+                }
+            }
+            Some(other) => {
+                return Err(ShellError::InvalidOption(format!("dircolors: {}", other)));
+            }
+        }
+        Ok(())
+    }
+
+    fn help(&self) -> &str {
+        "Usage: dircolors [-b|-c|-p] - Print the default LS_COLORS as sh or csh code, or as a readable list"
+    }
+}
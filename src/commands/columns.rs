@@ -0,0 +1,296 @@
+use std::io::{ self, Write };
+
+/// How `ls` arranges names when not in long format.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    /// `-C`: columns, filled top to bottom.
+    Columns,
+    /// `-x`: columns, filled left to right.
+    Across,
+    /// `-1`: one name per line.
+    OneLine,
+    /// `-m`: a comma-separated list, wrapped at the terminal width.
+    Commas,
+}
+
+impl Layout {
+    /// Columns on a terminal, one per line when piped or redirected.
+    pub fn default_for_stdout() -> Self {
+        if unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1 {
+            Layout::Columns
+        } else {
+            Layout::OneLine
+        }
+    }
+}
+
+/// A name ready to print. `text` may carry escape sequences, so the space it
+/// takes on screen is kept separately in `width`.
+pub struct Cell {
+    pub text: String,
+    pub width: usize,
+}
+
+const COLUMN_GAP: usize = 2;
+
+pub fn print_cells(cells: &[Cell], layout: Layout) -> io::Result<()> {
+    if cells.is_empty() {
+        return Ok(());
+    }
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let line_width = terminal_width();
+
+    match layout {
+        Layout::OneLine => {
+            for cell in cells {
+                writeln!(out, "{}", cell.text)?;
+            }
+        }
+        Layout::Commas => {
+            let mut used = 0;
+            for (i, cell) in cells.iter().enumerate() {
+                let last = i + 1 == cells.len();
+                let width = cell.width + if last { 0 } else { 1 };
+                if used > 0 && used + 1 + width > line_width {
+                    writeln!(out)?;
+                    used = 0;
+                } else if used > 0 {
+                    write!(out, " ")?;
+                    used += 1;
+                }
+                write!(out, "{}{}", cell.text, if last { "" } else { "," })?;
+                used += width;
+            }
+            writeln!(out)?;
+        }
+        Layout::Columns | Layout::Across => {
+            let across = layout == Layout::Across;
+            let (rows, widths) = fit_columns(cells, line_width, across);
+            let columns = widths.len();
+            for row in 0..rows {
+                for (column, width) in widths.iter().enumerate() {
+                    let index = if across { row * columns + column } else { column * rows + row };
+                    let Some(cell) = cells.get(index) else {
+                        break;
+                    };
+                    let next = if across { index + 1 } else { index + rows };
+                    // Nothing is padded after the last name on a line.
+                    if column + 1 == columns || next >= cells.len() {
+                        write!(out, "{}", cell.text)?;
+                        break;
+                    }
+                    write!(out, "{}{:pad$}", cell.text, "", pad = width - cell.width)?;
+                }
+                writeln!(out)?;
+            }
+        }
+    }
+    out.flush()
+}
+
+/// Picks the most columns that fit in `line_width`, returning the number of
+/// rows and the width of each column including the gap after it.
+fn fit_columns(cells: &[Cell], line_width: usize, across: bool) -> (usize, Vec<usize>) {
+    let count = cells.len();
+    // Even the narrowest names need one character and a gap each.
+    let max_columns = (line_width / (1 + COLUMN_GAP)).clamp(1, count);
+
+    for columns in (1..=max_columns).rev() {
+        let rows = count.div_ceil(columns);
+        // With fewer names than slots the last columns may end up empty;
+        // that layout is the same as one with fewer columns.
+        let used_columns = count.div_ceil(rows);
+        if used_columns != columns && !across {
+            continue;
+        }
+        let mut widths = vec![0; columns];
+        for (index, cell) in cells.iter().enumerate() {
+            let column = if across { index % columns } else { index / rows };
+            widths[column] = widths[column].max(cell.width + COLUMN_GAP);
+        }
+        let total: usize = widths.iter().sum::<usize>() - COLUMN_GAP;
+        if total <= line_width {
+            return (rows, widths);
+        }
+    }
+
+    let widest = cells.iter().map(|cell| cell.width).max().unwrap_or(0);
+    (count, vec![widest + COLUMN_GAP])
+}
+
+/// The terminal width from the tty, then `$COLUMNS`, then 80.
+pub fn terminal_width() -> usize {
+    unsafe {
+        let mut size: libc::winsize = std::mem::zeroed();
+        if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) == 0 && size.ws_col > 0 {
+            return size.ws_col as usize;
+        }
+    }
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|value| value.trim().parse::<usize>().ok())
+        .filter(|&width| width > 0)
+        .unwrap_or(80)
+}
+
+/// Columns a string takes on a terminal: combining marks and control
+/// characters take none, East Asian wide characters and emoji take two.
+pub fn display_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+fn char_width(c: char) -> usize {
+    let code = c as u32;
+    if code < 0x20 || (0x7f..0xa0).contains(&code) {
+        return 0;
+    }
+    if ZERO_WIDTH.iter().any(|&(start, end)| (start..=end).contains(&code)) {
+        return 0;
+    }
+    if WIDE.iter().any(|&(start, end)| (start..=end).contains(&code)) {
+        return 2;
+    }
+    1
+}
+
+const ZERO_WIDTH: &[(u32, u32)] = &[
+    (0x0300, 0x036f), // combining diacritical marks
+    (0x0483, 0x0489),
+    (0x0591, 0x05bd),
+    (0x0610, 0x061a),
+    (0x064b, 0x065f),
+    (0x0e31, 0x0e31),
+    (0x0e34, 0x0e3a),
+    (0x0e47, 0x0e4e),
+    (0x1ab0, 0x1aff),
+    (0x1dc0, 0x1dff),
+    (0x200b, 0x200f), // zero-width space, joiners, direction marks
+    (0x2028, 0x202e),
+    (0x2060, 0x2064),
+    (0x20d0, 0x20ff),
+    (0xfe00, 0xfe0f), // variation selectors
+    (0xfe20, 0xfe2f),
+    (0xfeff, 0xfeff),
+    (0xe0100, 0xe01ef),
+];
+
+const WIDE: &[(u32, u32)] = &[
+    (0x1100, 0x115f), // Hangul Jamo
+    (0x231a, 0x231b),
+    (0x2329, 0x232a),
+    (0x23e9, 0x23ec),
+    (0x23f0, 0x23f0),
+    (0x23f3, 0x23f3),
+    (0x25fd, 0x25fe),
+    (0x2614, 0x2615),
+    (0x2648, 0x2653),
+    (0x267f, 0x267f),
+    (0x2693, 0x2693),
+    (0x26a1, 0x26a1),
+    (0x26aa, 0x26ab),
+    (0x26bd, 0x26be),
+    (0x26c4, 0x26c5),
+    (0x26ce, 0x26ce),
+    (0x26d4, 0x26d4),
+    (0x26ea, 0x26ea),
+    (0x26f2, 0x26f3),
+    (0x26f5, 0x26f5),
+    (0x26fa, 0x26fa),
+    (0x26fd, 0x26fd),
+    (0x2705, 0x2705),
+    (0x270a, 0x270b),
+    (0x2728, 0x2728),
+    (0x274c, 0x274c),
+    (0x274e, 0x274e),
+    (0x2753, 0x2755),
+    (0x2757, 0x2757),
+    (0x2795, 0x2797),
+    (0x27b0, 0x27b0),
+    (0x27bf, 0x27bf),
+    (0x2b1b, 0x2b1c),
+    (0x2b50, 0x2b50),
+    (0x2b55, 0x2b55),
+    (0x2e80, 0x303e), // CJK radicals, punctuation
+    (0x3041, 0x33ff), // kana, CJK compatibility
+    (0x3400, 0x4dbf), // CJK extension A
+    (0x4e00, 0x9fff), // CJK unified ideographs
+    (0xa000, 0xa4cf), // Yi
+    (0xa960, 0xa97f),
+    (0xac00, 0xd7a3), // Hangul syllables
+    (0xf900, 0xfaff), // CJK compatibility ideographs
+    (0xfe10, 0xfe19),
+    (0xfe30, 0xfe6f),
+    (0xff00, 0xff60), // fullwidth forms
+    (0xffe0, 0xffe6),
+    (0x16fe0, 0x16fe4),
+    (0x17000, 0x18cff),
+    (0x1b000, 0x1b2ff),
+    (0x1f004, 0x1f004),
+    (0x1f0cf, 0x1f0cf),
+    (0x1f18e, 0x1f18e),
+    (0x1f191, 0x1f19a),
+    (0x1f200, 0x1f251),
+    (0x1f300, 0x1f64f), // pictographs and emoticons
+    (0x1f680, 0x1f6ff),
+    (0x1f7e0, 0x1f7eb),
+    (0x1f90c, 0x1f9ff),
+    (0x1fa70, 0x1faff),
+    (0x20000, 0x2fffd),
+    (0x30000, 0x3fffd),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cells(names: &[&str]) -> Vec<Cell> {
+        names.iter().map(|name| Cell { text: name.to_string(), width: display_width(name) }).collect()
+    }
+
+    #[test]
+    fn display_width_counts_terminal_columns() {
+        assert_eq!(display_width("plain"), 5);
+        assert_eq!(display_width("日本語"), 6);
+        assert_eq!(display_width("e\u{301}te\u{301}"), 3);
+        assert_eq!(display_width("a😀b"), 4);
+        assert_eq!(display_width("tab\there"), 7);
+        assert_eq!(display_width(""), 0);
+    }
+
+    #[test]
+    fn columns_fill_down_without_empty_columns() {
+        let names = cells(&["aaa", "bbb", "ccc", "ddd", "eee", "fff"]);
+        // Four columns would need two rows and leave the last one empty.
+        assert_eq!(fit_columns(&names, 20, false), (2, vec![5, 5, 5]));
+        assert_eq!(fit_columns(&names, 20, true), (2, vec![5, 5, 5, 5]));
+        assert_eq!(fit_columns(&names, 80, false), (1, vec![5; 6]));
+    }
+
+    #[test]
+    fn column_widths_follow_their_widest_name() {
+        let names = cells(&["a", "bbbbbb", "c", "d"]);
+        assert_eq!(fit_columns(&names, 12, false), (2, vec![8, 3]));
+        assert_eq!(fit_columns(&names, 12, true), (2, vec![3, 8, 3]));
+    }
+
+    #[test]
+    fn wide_characters_take_two_columns() {
+        let names = cells(&["日本語", "中文字"]);
+        assert_eq!(fit_columns(&names, 14, false), (1, vec![8, 8]));
+        assert_eq!(fit_columns(&names, 13, false), (2, vec![8]));
+    }
+
+    #[test]
+    fn names_wider_than_the_terminal_go_one_per_line() {
+        let names = cells(&["a-very-long-name", "another-long-name"]);
+        assert_eq!(fit_columns(&names, 10, false), (2, vec![19]));
+        assert_eq!(fit_columns(&names, 10, true), (2, vec![19]));
+    }
+
+    #[test]
+    fn a_single_name_is_one_cell() {
+        assert_eq!(fit_columns(&cells(&["only"]), 80, false), (1, vec![6]));
+        assert_eq!(fit_columns(&cells(&["much-too-long"]), 4, false), (1, vec![15]));
+    }
+}
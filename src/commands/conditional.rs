@@ -0,0 +1,424 @@
+use crate::commands::predicates;
+use crate::commands::{ text_args, CommandExecutor };
+use crate::encoding;
+use crate::error::ShellError;
+use std::ffi::{ CString, OsString };
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+
+pub struct TestCommand;
+pub struct BracketCommand;
+
+/// An expanded `[[ ]]` argument. Words keep track of which pieces were quoted,
+/// since quoted text is matched literally on the right of `==` and `=~`.
+pub enum CondArg {
+    Word(Vec<(String, bool)>),
+    Operator(&'static str),
+}
+
+impl CommandExecutor for TestCommand {
+    fn execute(&self, args: &[OsString]) -> Result<(), ShellError> {
+        status(evaluate_test("test", &text_args(args))?)
+    }
+
+    fn help(&self) -> &str {
+        "Usage: test expression - Evaluate a conditional expression (file, string and integer tests)"
+    }
+}
+
+impl CommandExecutor for BracketCommand {
+    fn execute(&self, args: &[OsString]) -> Result<(), ShellError> {
+        match text_args(args).split_last() {
+            Some((last, rest)) if last == "]" => status(evaluate_test("[", rest)?),
+            _ => Err(ShellError::ExecutionError("[: missing ']'".to_string())),
+        }
+    }
+
+    fn help(&self) -> &str {
+        "Usage: [ expression ] - Same as test, with a closing bracket"
+    }
+}
+
+fn status(result: bool) -> Result<(), ShellError> {
+    if result {
+        Ok(())
+    } else {
+        Err(ShellError::ExitStatus(1))
+    }
+}
+
+const UNARY_OPS: &[&str] = &[
+    "-e", "-f", "-d", "-r", "-w", "-x", "-s", "-L", "-h", "-p", "-S", "-b", "-c",
+    "-g", "-u", "-k", "-O", "-G", "-z", "-n", "-t",
+];
+
+const BINARY_OPS: &[&str] = &[
+    "=", "==", "!=", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+];
+
+fn evaluate_test(name: &str, args: &[String]) -> Result<bool, ShellError> {
+    let mut parser = TestParser { name, args, pos: 0 };
+    if args.is_empty() {
+        return Ok(false);
+    }
+    let result = parser.parse_or()?;
+    if let Some(extra) = args.get(parser.pos) {
+        return Err(ShellError::ExecutionError(format!("{}: {}: unexpected argument", name, extra)));
+    }
+    Ok(result)
+}
+
+struct TestParser<'a> {
+    name: &'a str,
+    args: &'a [String],
+    pos: usize,
+}
+
+impl TestParser<'_> {
+    fn peek(&self) -> Option<&str> {
+        self.args.get(self.pos).map(|s| s.as_str())
+    }
+
+    fn remaining(&self) -> usize {
+        self.args.len() - self.pos
+    }
+
+    fn parse_or(&mut self) -> Result<bool, ShellError> {
+        let mut result = self.parse_and()?;
+        while self.peek() == Some("-o") {
+            self.pos += 1;
+            let right = self.parse_and()?;
+            result = result || right;
+        }
+        Ok(result)
+    }
+
+    fn parse_and(&mut self) -> Result<bool, ShellError> {
+        let mut result = self.parse_not()?;
+        while self.peek() == Some("-a") {
+            self.pos += 1;
+            let right = self.parse_not()?;
+            result = result && right;
+        }
+        Ok(result)
+    }
+
+    fn parse_not(&mut self) -> Result<bool, ShellError> {
+        // A lone `!` is a string, not an operator.
+        if self.peek() == Some("!") && self.remaining() > 1 {
+            self.pos += 1;
+            return Ok(!self.parse_not()?);
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<bool, ShellError> {
+        let args = self.args;
+        let Some(first) = args.get(self.pos).map(String::as_str) else {
+            return Err(ShellError::ExecutionError(format!("{}: argument expected", self.name)));
+        };
+
+        // Binary operators take priority so that `test -f = -f` compares strings.
+        if self.remaining() >= 3 {
+            let op = self.args[self.pos + 1].as_str();
+            if BINARY_OPS.contains(&op) {
+                let left = &self.args[self.pos];
+                let right = &self.args[self.pos + 2];
+                self.pos += 3;
+                return binary_test(self.name, op, left, right);
+            }
+        }
+
+        if first == "(" && self.remaining() >= 3 {
+            self.pos += 1;
+            let result = self.parse_or()?;
+            if self.peek() != Some(")") {
+                return Err(ShellError::ExecutionError(format!("{}: ')' expected", self.name)));
+            }
+            self.pos += 1;
+            return Ok(result);
+        }
+
+        if UNARY_OPS.contains(&first) && self.remaining() >= 2 {
+            let operand = &self.args[self.pos + 1];
+            self.pos += 2;
+            return unary_test(self.name, first, operand);
+        }
+
+        self.pos += 1;
+        Ok(!first.is_empty())
+    }
+}
+
+/// Evaluates the tokens between `[[` and `]]`.
+pub fn evaluate_conditional(args: &[CondArg]) -> Result<bool, ShellError> {
+    let mut parser = CondParser { args, pos: 0 };
+    if args.is_empty() {
+        return Err(ShellError::ParseError("[[: expression expected".to_string()));
+    }
+    let result = parser.parse_or()?;
+    if parser.pos < args.len() {
+        return Err(ShellError::ParseError(
+            format!("syntax error in conditional expression near '{}'", parser.text(parser.pos))
+        ));
+    }
+    Ok(result)
+}
+
+struct CondParser<'a> {
+    args: &'a [CondArg],
+    pos: usize,
+}
+
+impl CondParser<'_> {
+    fn is_operator(&self, op: &str) -> bool {
+        matches!(self.args.get(self.pos), Some(CondArg::Operator(o)) if *o == op)
+    }
+
+    /// The unquoted text of a word, used to recognise operators.
+    fn bare_text(&self, pos: usize) -> Option<String> {
+        match self.args.get(pos) {
+            Some(CondArg::Word(segments)) if segments.iter().all(|(_, quoted)| !quoted) => {
+                Some(segments.iter().map(|(text, _)| text.as_str()).collect())
+            }
+            _ => None,
+        }
+    }
+
+    fn text(&self, pos: usize) -> String {
+        match self.args.get(pos) {
+            Some(CondArg::Word(segments)) => segments.iter().map(|(text, _)| text.as_str()).collect(),
+            Some(CondArg::Operator(op)) => op.to_string(),
+            None => String::new(),
+        }
+    }
+
+    fn expect_word(&self, pos: usize) -> Result<(), ShellError> {
+        match self.args.get(pos) {
+            Some(CondArg::Word(_)) => Ok(()),
+            _ => Err(ShellError::ParseError(
+                format!("syntax error in conditional expression near '{}'", self.text(pos))
+            )),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<bool, ShellError> {
+        let mut result = self.parse_and()?;
+        while self.is_operator("||") {
+            self.pos += 1;
+            // Evaluated for syntax even when short-circuited.
+            let right = self.parse_and()?;
+            result = result || right;
+        }
+        Ok(result)
+    }
+
+    fn parse_and(&mut self) -> Result<bool, ShellError> {
+        let mut result = self.parse_not()?;
+        while self.is_operator("&&") {
+            self.pos += 1;
+            let right = self.parse_not()?;
+            result = result && right;
+        }
+        Ok(result)
+    }
+
+    fn parse_not(&mut self) -> Result<bool, ShellError> {
+        if self.bare_text(self.pos).as_deref() == Some("!") {
+            self.pos += 1;
+            return Ok(!self.parse_not()?);
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<bool, ShellError> {
+        if self.is_operator("(") {
+            self.pos += 1;
+            let result = self.parse_or()?;
+            if !self.is_operator(")") {
+                return Err(ShellError::ParseError("[[: ')' expected".to_string()));
+            }
+            self.pos += 1;
+            return Ok(result);
+        }
+
+        self.expect_word(self.pos)?;
+        let left = self.text(self.pos);
+
+        let op = match self.args.get(self.pos + 1) {
+            Some(CondArg::Operator(op @ ("<" | ">"))) => Some(op.to_string()),
+            Some(CondArg::Word(_)) => self.bare_text(self.pos + 1)
+                .filter(|op| op == "=~" || BINARY_OPS.contains(&op.as_str())),
+            _ => None,
+        };
+        if let Some(op) = op {
+            self.expect_word(self.pos + 2)?;
+            let right = match &self.args[self.pos + 2] {
+                CondArg::Word(segments) => segments,
+                CondArg::Operator(_) => unreachable!(),
+            };
+            self.pos += 3;
+            return match op.as_str() {
+                "=" | "==" => Ok(glob_match(&glob_pattern(right), &left)),
+                "!=" => Ok(!glob_match(&glob_pattern(right), &left)),
+                "=~" => regex_match(&regex_pattern(right), &left),
+                _ => binary_test("[[", &op, &left, &join(right)),
+            };
+        }
+
+        if let Some(op) = self.bare_text(self.pos)
+            && UNARY_OPS.contains(&op.as_str())
+            && matches!(self.args.get(self.pos + 1), Some(CondArg::Word(_)))
+        {
+            let operand = self.text(self.pos + 1);
+            self.pos += 2;
+            return unary_test("[[", &op, &operand);
+        }
+
+        self.pos += 1;
+        Ok(!left.is_empty())
+    }
+}
+
+fn join(segments: &[(String, bool)]) -> String {
+    segments.iter().map(|(text, _)| text.as_str()).collect()
+}
+
+/// Builds an `fnmatch` pattern in which quoted text only matches itself.
+fn glob_pattern(segments: &[(String, bool)]) -> String {
+    let mut pattern = String::new();
+    for (text, quoted) in segments {
+        for ch in text.chars() {
+            if *quoted && matches!(ch, '*' | '?' | '[' | ']' | '\\') {
+                pattern.push('\\');
+            }
+            pattern.push(ch);
+        }
+    }
+    pattern
+}
+
+/// Builds an extended regular expression in which quoted text only matches
+/// itself.
+fn regex_pattern(segments: &[(String, bool)]) -> String {
+    let mut pattern = String::new();
+    for (text, quoted) in segments {
+        for ch in text.chars() {
+            if *quoted && "\\^$.|?*+()[]{}".contains(ch) {
+                pattern.push('\\');
+            }
+            pattern.push(ch);
+        }
+    }
+    pattern
+}
+
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let (Ok(pattern), Ok(text)) = (CString::new(encoding::encode_bytes(pattern)), CString::new(encoding::encode_bytes(text))) else {
+        return false;
+    };
+    unsafe { libc::fnmatch(pattern.as_ptr(), text.as_ptr(), 0) == 0 }
+}
+
+fn regex_match(pattern: &str, text: &str) -> Result<bool, ShellError> {
+    let invalid = || ShellError::ExecutionError(format!("[[: {}: invalid regular expression", pattern));
+    let c_pattern = CString::new(encoding::encode_bytes(pattern)).map_err(|_| invalid())?;
+    let Ok(c_text) = CString::new(encoding::encode_bytes(text)) else {
+        return Ok(false);
+    };
+
+    unsafe {
+        let mut regex: libc::regex_t = std::mem::zeroed();
+        if libc::regcomp(&mut regex, c_pattern.as_ptr(), libc::REG_EXTENDED | libc::REG_NOSUB) != 0 {
+            return Err(invalid());
+        }
+        let result = libc::regexec(&regex, c_text.as_ptr(), 0, std::ptr::null_mut(), 0);
+        libc::regfree(&mut regex);
+        Ok(result == 0)
+    }
+}
+
+fn unary_test(name: &str, op: &str, operand: &str) -> Result<bool, ShellError> {
+    let encoded = encoding::encode(operand);
+    let path = Path::new(&encoded);
+    Ok(match op {
+        "-z" => operand.is_empty(),
+        "-n" => !operand.is_empty(),
+        "-t" => {
+            let fd = parse_integer(name, operand)?;
+            unsafe { libc::isatty(fd as libc::c_int) == 1 }
+        }
+        "-L" | "-h" => fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink()),
+        "-r" => predicates::accessible(path, libc::R_OK),
+        "-w" => predicates::accessible(path, libc::W_OK),
+        "-x" => predicates::accessible(path, libc::X_OK),
+        _ => {
+            let Ok(meta) = fs::metadata(path) else {
+                return Ok(false);
+            };
+            let ftype = meta.file_type();
+            match op {
+                "-e" => true,
+                "-f" => ftype.is_file(),
+                "-d" => ftype.is_dir(),
+                "-s" => meta.len() > 0,
+                "-p" => ftype.is_fifo(),
+                "-S" => ftype.is_socket(),
+                "-b" => ftype.is_block_device(),
+                "-c" => ftype.is_char_device(),
+                "-u" => predicates::is_setuid(&meta),
+                "-g" => predicates::is_setgid(&meta),
+                "-k" => predicates::is_sticky(&meta),
+                "-O" => predicates::owned_by_effective_user(&meta),
+                "-G" => predicates::owned_by_effective_group(&meta),
+                _ => unreachable!("unknown unary test {}", op),
+            }
+        }
+    })
+}
+
+fn binary_test(name: &str, op: &str, left: &str, right: &str) -> Result<bool, ShellError> {
+    Ok(match op {
+        "=" | "==" => left == right,
+        "!=" => left != right,
+        "<" => left < right,
+        ">" => left > right,
+        "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" => {
+            let (a, b) = (parse_integer(name, left)?, parse_integer(name, right)?);
+            match op {
+                "-eq" => a == b,
+                "-ne" => a != b,
+                "-lt" => a < b,
+                "-le" => a <= b,
+                "-gt" => a > b,
+                _ => a >= b,
+            }
+        }
+        "-nt" | "-ot" => {
+            let left = predicates::modified(Path::new(&encoding::encode(left)));
+            let right = predicates::modified(Path::new(&encoding::encode(right)));
+            // A file that exists is newer than one that does not.
+            match (left, right) {
+                (Some(l), Some(r)) => if op == "-nt" { l > r } else { l < r },
+                (Some(_), None) => op == "-nt",
+                (None, Some(_)) => op == "-ot",
+                (None, None) => false,
+            }
+        }
+        "-ef" => match (fs::metadata(encoding::encode(left)), fs::metadata(encoding::encode(right))) {
+            (Ok(l), Ok(r)) => predicates::same_file(&l, &r),
+            _ => false,
+        },
+        _ => unreachable!("unknown binary test {}", op),
+    })
+}
+
+/// A number operand that is not one is a usage error, which like other
+/// shells reports status 2 rather than plain false.
+fn parse_integer(name: &str, value: &str) -> Result<i64, ShellError> {
+    value.trim().parse().map_err(|_| {
+        eprintln!("{}: {}: integer expression expected", name, value);
+        ShellError::ExitStatus(2)
+    })
+}
@@ -0,0 +1,835 @@
+use crate::commands::overwrite::OverwritePolicy;
+use crate::commands::progress::Progress;
+use crate::commands::quoting::{ self, QuotingStyle };
+use crate::commands::xattr;
+use crate::trap;
+use std::ffi::CString;
+use std::collections::{ HashMap, HashSet };
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{ DirBuilderExt, FileExt, FileTypeExt, MetadataExt, OpenOptionsExt, PermissionsExt };
+use std::os::unix::io::AsRawFd;
+use std::path::{ Path, PathBuf };
+use std::sync::{ Arc, Mutex, mpsc };
+use std::thread;
+
+/// The most one `copy_file_range` call moves, so that the progress line
+/// keeps up with large files.
+const RANGE_CHUNK: usize = 64 << 20;
+/// The buffer for copies that go through user space.
+const BUFFER_SIZE: usize = 1 << 20;
+/// The size of the zero blocks `--sparse=always` turns into holes.
+const SPARSE_BLOCK: usize = 4096;
+
+/// What `cp` carries over besides the contents, from `-p`, `-a` and
+/// `--preserve=LIST`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Preserve {
+    pub mode: bool,
+    pub ownership: bool,
+    pub timestamps: bool,
+    pub xattr: bool,
+    pub links: bool,
+}
+
+impl Preserve {
+    /// What `-p` and a bare `--preserve` keep.
+    pub fn add_basic(&mut self) {
+        self.mode = true;
+        self.ownership = true;
+        self.timestamps = true;
+    }
+
+    pub fn all() -> Self {
+        Preserve { mode: true, ownership: true, timestamps: true, xattr: true, links: true }
+    }
+
+    /// Adds the attributes in a `--preserve` list such as
+    /// `mode,timestamps`. Returns the first word it does not know.
+    pub fn add_list<'a>(&mut self, list: &'a str) -> Result<(), &'a str> {
+        for word in list.split(',') {
+            match word {
+                "mode" => self.mode = true,
+                "ownership" => self.ownership = true,
+                "timestamps" => self.timestamps = true,
+                "xattr" => self.xattr = true,
+                "links" => self.links = true,
+                "all" => *self = Preserve::all(),
+                _ => return Err(word),
+            }
+        }
+        Ok(())
+    }
+
+    fn any(&self) -> bool {
+        *self != Preserve::default()
+    }
+}
+
+/// `--reflink`: whether a file is cloned, sharing its blocks with the
+/// source until either is written, on filesystems that can (btrfs, XFS).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Reflink {
+    #[default]
+    Never,
+    /// Clone where possible and copy elsewhere.
+    Auto,
+    /// Clone or fail.
+    Always,
+}
+
+impl Reflink {
+    pub fn parse(word: &str) -> Option<Self> {
+        match word {
+            "never" => Some(Reflink::Never),
+            "auto" => Some(Reflink::Auto),
+            "always" => Some(Reflink::Always),
+            _ => None,
+        }
+    }
+}
+
+/// `--sparse`: which parts of a copy are left as holes.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Sparse {
+    /// The holes of a source that has some.
+    #[default]
+    Auto,
+    /// Those, and every block of zeros.
+    Always,
+    /// None: every byte is written.
+    Never,
+}
+
+impl Sparse {
+    pub fn parse(word: &str) -> Option<Self> {
+        match word {
+            "auto" => Some(Sparse::Auto),
+            "always" => Some(Sparse::Always),
+            "never" => Some(Sparse::Never),
+            _ => None,
+        }
+    }
+}
+
+/// Which symlinks are followed.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Dereference {
+    /// `-P`, and the default under `-r`: symlinks are copied as symlinks.
+    Never,
+    /// `-H`: only those named on the command line are followed.
+    CommandLine,
+    /// `-L`, and the default without `-r`.
+    #[default]
+    Always,
+}
+
+#[derive(Debug, Clone)]
+pub struct CopyOptions {
+    pub recursive: bool,
+    pub preserve: Preserve,
+    pub dereference: Dereference,
+    pub reflink: Reflink,
+    pub sparse: Sparse,
+    pub overwrite: OverwritePolicy,
+    /// `-v`: print each file as it is copied.
+    pub verbose: bool,
+    pub progress: Option<Arc<Progress>>,
+    /// `-j`: how many files of a tree are copied at once.
+    pub jobs: usize,
+    pub links: Arc<LinkMap>,
+}
+
+/// The copies made of files with more than one link, by the source's
+/// (dev, inode). With `--preserve=links` a later name of the same file
+/// becomes a hard link to the first copy instead of another copy. Only
+/// copies that were written are recorded, so a name skipped by the
+/// overwrite policy or a failed copy is never linked to.
+#[derive(Debug, Default)]
+pub struct LinkMap {
+    copies: Mutex<HashMap<(u64, u64), PathBuf>>,
+}
+
+impl CopyOptions {
+    pub fn new() -> Self {
+        CopyOptions {
+            recursive: false,
+            preserve: Preserve::default(),
+            dereference: Dereference::default(),
+            reflink: Reflink::default(),
+            sparse: Sparse::default(),
+            overwrite: OverwritePolicy::new("cp"),
+            verbose: false,
+            progress: None,
+            jobs: thread::available_parallelism().map_or(1, |jobs| jobs.get()),
+            links: Arc::default(),
+        }
+    }
+
+    /// What `mv` copies with when it cannot rename: everything, as
+    /// `cp -a` would.
+    pub fn for_move() -> Self {
+        CopyOptions {
+            recursive: true,
+            preserve: Preserve::all(),
+            dereference: Dereference::Never,
+            overwrite: OverwritePolicy::new("mv"),
+            ..CopyOptions::new()
+        }
+    }
+
+    /// stat or lstat, as the dereference mode says. `named` is true for
+    /// paths given on the command line.
+    pub fn metadata(&self, path: &Path, named: bool) -> io::Result<fs::Metadata> {
+        let follow = match self.dereference {
+            Dereference::Never => false,
+            Dereference::CommandLine => named,
+            Dereference::Always => true,
+        };
+        if follow { fs::metadata(path) } else { fs::symlink_metadata(path) }
+    }
+
+    /// Under `--preserve=links`, the copy already made of the file
+    /// `metadata` describes. A file with a single name left can still have
+    /// been copied by another, as when `mv` has moved that one already.
+    fn earlier_copy(&self, metadata: &fs::Metadata) -> Option<PathBuf> {
+        if !self.preserve.links || metadata.is_dir() {
+            return None;
+        }
+        self.links.copies.lock().unwrap().get(&(metadata.dev(), metadata.ino())).cloned()
+    }
+
+    /// Records `dst` as the copy of the file `metadata` describes, once it
+    /// has been written, if other names of that file may follow.
+    fn record_copy(&self, metadata: &fs::Metadata, dst: &Path) {
+        if self.preserve.links && !metadata.is_dir() && metadata.nlink() >= 2 {
+            let mut copies = self.links.copies.lock().unwrap();
+            copies.entry((metadata.dev(), metadata.ino())).or_insert_with(|| dst.to_path_buf());
+        }
+    }
+}
+
+/// Copies anything but a directory. `metadata` is the source's, as
+/// returned by `CopyOptions::metadata`: a symlink there is copied as a
+/// symlink. Under `-r` FIFOs, sockets and device nodes are made anew;
+/// otherwise their contents are read, so `cp /dev/null file` empties a file.
+/// An existing `dst` is left alone if the overwrite policy says so. Under
+/// `--preserve=links` a file already copied by another name is linked to
+/// that copy.
+pub fn copy_file(src: &Path, dst: &Path, metadata: &fs::Metadata, options: &CopyOptions) -> io::Result<()> {
+    transfer(src, dst, metadata, options)
+}
+
+/// Copies `src` to `dst`, or makes `dst` a hard link to an earlier copy of
+/// the same file.
+fn transfer(src: &Path, dst: &Path, metadata: &fs::Metadata, options: &CopyOptions) -> io::Result<()> {
+    let progress = options.progress.as_deref();
+    if let Some(progress) = progress {
+        progress.start_file(src);
+    }
+    let size = if metadata.is_file() { metadata.len() } else { 0 };
+    let copied = if options.overwrite.allows(metadata, dst)? {
+        let copied = match options.earlier_copy(metadata) {
+            Some(first) => {
+                remove_existing(dst)?;
+                fs::hard_link(first, dst)?;
+                0
+            }
+            None => {
+                let copied = write_copy(src, dst, metadata, options)?;
+                options.record_copy(metadata, dst);
+                copied
+            }
+        };
+        report(src, dst, options);
+        copied
+    } else {
+        0
+    };
+    if let Some(progress) = progress {
+        progress.finish_file(size.saturating_sub(copied));
+    }
+    Ok(())
+}
+
+/// Does the copying for `copy_file`. Returns the bytes already reported to
+/// the progress line.
+fn write_copy(src: &Path, dst: &Path, metadata: &fs::Metadata, options: &CopyOptions) -> io::Result<u64> {
+    let ftype = metadata.file_type();
+    let special = ftype.is_fifo() || ftype.is_socket() || ftype.is_char_device() || ftype.is_block_device();
+    let mut copied = 0;
+    if ftype.is_symlink() {
+        let target = fs::read_link(src)?;
+        remove_existing(dst)?;
+        std::os::unix::fs::symlink(target, dst)?;
+    } else if special && options.recursive {
+        remove_existing(dst)?;
+        make_node(dst, metadata)?;
+    } else if special {
+        // fs::copy only takes regular files.
+        let mut reader = fs::File::open(src)?;
+        let mut writer = fs::File::create(dst)?;
+        io::copy(&mut reader, &mut writer)?;
+    } else {
+        copied = copy_regular(src, dst, metadata, options)?;
+    }
+    if options.preserve.any() {
+        apply_attributes(src, dst, metadata, options.preserve)?;
+    }
+    Ok(copied)
+}
+
+/// Copies a regular file: as a clone if `--reflink` asks for one and the
+/// filesystem can make it, else with `copy_file_range`, which lets the
+/// kernel move the data without a trip through user space. Holes are kept
+/// as `--sparse` says. Returns the bytes reported to the progress line.
+fn copy_regular(src: &Path, dst: &Path, metadata: &fs::Metadata, options: &CopyOptions) -> io::Result<u64> {
+    let reader = fs::File::open(src)?;
+    let existed = fs::symlink_metadata(dst).is_ok();
+    // Not truncated yet, so that a failed clone leaves the file as it was.
+    let writer = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(metadata.mode() & 0o7777)
+        .open(dst)?;
+
+    let mut contents = Contents {
+        reader: &reader,
+        writer: &writer,
+        progress: options.progress.as_deref(),
+        copied: 0,
+        use_range: true,
+    };
+    let cloned = options.reflink != Reflink::Never && {
+        let rc = unsafe { libc::ioctl(writer.as_raw_fd(), libc::FICLONE, reader.as_raw_fd()) };
+        if rc != 0 && options.reflink == Reflink::Always {
+            let error = io::Error::last_os_error();
+            if !existed {
+                let _ = fs::remove_file(dst);
+            }
+            return Err(error);
+        }
+        rc == 0
+    };
+    if !cloned {
+        writer.set_len(0)?;
+        let len = metadata.len();
+        match options.sparse {
+            Sparse::Never => contents.range(0, len, false)?,
+            // A file with fewer blocks than its size needs has holes.
+            Sparse::Auto if metadata.blocks() * 512 >= len => contents.range(0, len, false)?,
+            sparse => contents.extents(len, sparse == Sparse::Always)?,
+        }
+    }
+    // Like fs::copy, an existing file gets the source's mode too.
+    writer.set_permissions(metadata.permissions())?;
+    Ok(contents.copied)
+}
+
+/// The two ends of a regular file copy.
+struct Contents<'a> {
+    reader: &'a fs::File,
+    writer: &'a fs::File,
+    progress: Option<&'a Progress>,
+    copied: u64,
+    /// Cleared when `copy_file_range` cannot be used for these files, as
+    /// on kernels without it or between some filesystems.
+    use_range: bool,
+}
+
+impl Contents<'_> {
+    /// Copies `len` bytes at `offset` to the same place in the copy, less
+    /// if the source has shrunk. With `skip_zeros` blocks of zeros are
+    /// skipped over, leaving holes.
+    fn range(&mut self, offset: u64, len: u64, skip_zeros: bool) -> io::Result<()> {
+        let end = offset + len;
+        let mut pos = offset;
+        while pos < end && self.use_range && !skip_zeros {
+            trap::check_interrupted()?;
+            let mut offset_in = pos as libc::loff_t;
+            let mut offset_out = pos as libc::loff_t;
+            let want = (end - pos).min(RANGE_CHUNK as u64) as usize;
+            let done = unsafe {
+                libc::copy_file_range(
+                    self.reader.as_raw_fd(),
+                    &mut offset_in,
+                    self.writer.as_raw_fd(),
+                    &mut offset_out,
+                    want,
+                    0
+                )
+            };
+            if done < 0 {
+                let error = io::Error::last_os_error();
+                match error.raw_os_error() {
+                    Some(libc::EINTR) => trap::check_interrupted()?,
+                    Some(libc::ENOSYS | libc::EXDEV | libc::EINVAL | libc::EOPNOTSUPP) => self.use_range = false,
+                    _ => return Err(error),
+                }
+                continue;
+            }
+            if done == 0 {
+                return Ok(());
+            }
+            pos += done as u64;
+            self.count(done as u64);
+        }
+
+        let mut buffer = vec![0u8; (end - pos).min(BUFFER_SIZE as u64) as usize];
+        while pos < end {
+            trap::check_interrupted()?;
+            let want = (end - pos).min(buffer.len() as u64) as usize;
+            let read = match self.reader.read_at(&mut buffer[..want], pos) {
+                Ok(0) => return Ok(()),
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if skip_zeros {
+                for (i, block) in buffer[..read].chunks(SPARSE_BLOCK).enumerate() {
+                    // No early exit, so that the loop is vectorized.
+                    if block.iter().fold(0, |acc, &byte| acc | byte) != 0 {
+                        self.writer.write_all_at(block, pos + (i * SPARSE_BLOCK) as u64)?;
+                    }
+                }
+            } else {
+                self.writer.write_all_at(&buffer[..read], pos)?;
+            }
+            pos += read as u64;
+            self.count(read as u64);
+        }
+        Ok(())
+    }
+
+    /// Copies only the parts of the source that hold data, found with
+    /// `SEEK_DATA` and `SEEK_HOLE`, and leaves the rest as holes.
+    fn extents(&mut self, len: u64, skip_zeros: bool) -> io::Result<()> {
+        let mut pos = 0;
+        while pos < len {
+            let data = match seek(self.reader, pos, libc::SEEK_DATA) {
+                Ok(data) => data,
+                // Nothing but a hole from here on.
+                Err(e) if e.raw_os_error() == Some(libc::ENXIO) => break,
+                // A filesystem that cannot tell: all of it is data.
+                Err(e) if e.raw_os_error() == Some(libc::EINVAL) => pos,
+                Err(e) => return Err(e),
+            };
+            if data >= len {
+                break;
+            }
+            let hole = seek(self.reader, data, libc::SEEK_HOLE).unwrap_or(len).min(len);
+            self.range(data, hole - data, skip_zeros)?;
+            pos = hole;
+        }
+        // Holes at the end are made by setting the length.
+        self.writer.set_len(len)
+    }
+
+    fn count(&mut self, bytes: u64) {
+        self.copied += bytes;
+        if let Some(progress) = self.progress {
+            progress.add(bytes);
+        }
+    }
+}
+
+fn seek(file: &fs::File, offset: u64, whence: libc::c_int) -> io::Result<u64> {
+    let pos = unsafe { libc::lseek(file.as_raw_fd(), offset as libc::off_t, whence) };
+    if pos < 0 { Err(io::Error::last_os_error()) } else { Ok(pos as u64) }
+}
+
+/// `-v`: `'src' -> 'dst'`, quoted the way GNU cp does.
+fn report(src: &Path, dst: &Path, options: &CopyOptions) {
+    if !options.verbose {
+        return;
+    }
+    if let Some(progress) = options.progress.as_deref() {
+        progress.clear();
+    }
+    println!("{} -> {}", quote_path(src), quote_path(dst));
+}
+
+pub fn quote_path(path: &Path) -> String {
+    quoting::quote(path.as_os_str(), QuotingStyle::ShellEscapeAlways)
+}
+
+/// The bytes in regular files and the number of files other than
+/// directories that copying `path` goes through, for the totals of
+/// `--progress`.
+pub fn measure(path: &Path, options: &CopyOptions) -> (u64, u64) {
+    measure_inner(path, options, true, &mut HashSet::new())
+}
+
+fn measure_inner(path: &Path, options: &CopyOptions, named: bool, seen: &mut HashSet<(u64, u64)>) -> (u64, u64) {
+    let Ok(metadata) = options.metadata(path, named) else {
+        return (0, 0);
+    };
+    if !metadata.is_dir() {
+        return (if metadata.is_file() { metadata.len() } else { 0 }, 1);
+    }
+    // A directory reached twice under -L is only counted once.
+    if !options.recursive || !seen.insert((metadata.dev(), metadata.ino())) {
+        return (0, 0);
+    }
+    let Ok(entries) = fs::read_dir(path) else {
+        return (0, 0);
+    };
+    entries
+        .flatten()
+        .map(|entry| measure_inner(&entry.path(), options, false, seen))
+        .fold((0, 0), |(bytes, files), (more_bytes, more_files)| (bytes + more_bytes, files + more_files))
+}
+
+/// Makes room for a symlink or device node, which unlike file contents
+/// cannot be written over an existing file.
+fn remove_existing(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.is_dir() => fs::remove_file(path),
+        _ => Ok(()),
+    }
+}
+
+fn make_node(path: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    // The file type bits come along with the mode.
+    let rc = unsafe { libc::mknod(c_path.as_ptr(), metadata.mode() as libc::mode_t, metadata.rdev() as libc::dev_t) };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A file that could not be copied. Copying a tree goes on past one, so
+/// there can be many.
+#[derive(Debug)]
+pub struct CopyError {
+    pub path: PathBuf,
+    pub error: io::Error,
+}
+
+/// A file found by the walk, waiting for a worker.
+struct FileJob {
+    src: PathBuf,
+    dst: PathBuf,
+    metadata: fs::Metadata,
+}
+
+/// A directory whose attributes are set once everything in it is done.
+struct DirJob {
+    src: PathBuf,
+    dst: PathBuf,
+    metadata: fs::Metadata,
+    created: bool,
+}
+
+/// Copies a directory and everything under it. The tree is walked once,
+/// in one thread, which makes each directory before anything that goes
+/// in it; the files are handed to `options.jobs` workers. A directory's
+/// own attributes are set at the end, deepest first, so that its
+/// timestamps are not changed again by the copying and a read-only mode
+/// does not get in the way. Whatever fails is skipped and returned, and
+/// the rest is still copied.
+pub fn copy_dir(src: &Path, dst: &Path, options: &CopyOptions) -> Vec<CopyError> {
+    let errors = Mutex::new(Vec::new());
+    let mut walk = Walk {
+        options,
+        errors: &errors,
+        ancestors: HashSet::new(),
+        dirs: Vec::new(),
+        linked: HashSet::new(),
+        links: Vec::new(),
+    };
+    let metadata = match fs::metadata(src) {
+        Ok(metadata) => metadata,
+        Err(error) => return vec![CopyError { path: src.to_path_buf(), error }],
+    };
+
+    if options.jobs <= 1 {
+        walk.dir(src, dst, metadata, &mut |job| copy_job(job, options, &errors));
+    } else {
+        // Bounded, so that the walk does not run far ahead of the copying
+        // on a large tree.
+        let (sender, receiver) = mpsc::sync_channel::<FileJob>(options.jobs * 4);
+        let receiver = Mutex::new(receiver);
+        thread::scope(|scope| {
+            for _ in 0..options.jobs {
+                let (receiver, errors) = (&receiver, &errors);
+                scope.spawn(move || {
+                    loop {
+                        // The lock is only held while waiting for a job.
+                        let job = receiver.lock().unwrap().recv();
+                        let Ok(job) = job else {
+                            break;
+                        };
+                        copy_job(job, options, errors);
+                    }
+                });
+            }
+            walk.dir(src, dst, metadata, &mut |job| {
+                // Only fails once every worker is gone.
+                let _ = sender.send(job);
+            });
+            // Lets the workers finish.
+            drop(sender);
+        });
+    }
+
+    for job in walk.links.drain(..) {
+        copy_job(job, options, &errors);
+    }
+    for dir in walk.dirs.iter().rev() {
+        if let Err(error) = finish_dir(dir, options) {
+            errors.lock().unwrap().push(CopyError { path: dir.src.clone(), error });
+        }
+    }
+    let mut errors = errors.into_inner().unwrap();
+    // A trapped signal stops the copy; that is reported once, for the
+    // whole tree, rather than for each file it cut short.
+    if trap::interrupted() {
+        errors.retain(|failure| failure.error.kind() != io::ErrorKind::Interrupted);
+        errors.push(CopyError { path: src.to_path_buf(), error: io::Error::from(io::ErrorKind::Interrupted) });
+    }
+    errors
+}
+
+/// What `mv` does when it cannot rename across filesystems: copies `src`
+/// with `CopyOptions::for_move` and removes it once all of it is copied.
+pub fn move_across(src: &Path, dst: &Path, options: &CopyOptions) -> Vec<CopyError> {
+    let metadata = match fs::symlink_metadata(src) {
+        Ok(metadata) => metadata,
+        Err(error) => return vec![CopyError { path: src.to_path_buf(), error }],
+    };
+    let mut errors = if metadata.is_dir() {
+        copy_dir(src, dst, options)
+    } else {
+        copy_file(src, dst, &metadata, options)
+            .err()
+            .map(|error| CopyError { path: src.to_path_buf(), error })
+            .into_iter()
+            .collect()
+    };
+    if errors.is_empty() {
+        let removed = if metadata.is_dir() { fs::remove_dir_all(src) } else { fs::remove_file(src) };
+        if let Err(error) = removed {
+            errors.push(CopyError { path: src.to_path_buf(), error });
+        }
+    }
+    errors
+}
+
+fn copy_job(job: FileJob, options: &CopyOptions, errors: &Mutex<Vec<CopyError>>) {
+    // What is left after a trapped signal is skipped.
+    if trap::interrupted() {
+        return;
+    }
+    if let Err(error) = transfer(&job.src, &job.dst, &job.metadata, options) {
+        errors.lock().unwrap().push(CopyError { path: job.src, error });
+    }
+}
+
+struct Walk<'a> {
+    options: &'a CopyOptions,
+    errors: &'a Mutex<Vec<CopyError>>,
+    /// The (dev, inode) of the directories being copied above the current
+    /// one. Under `-L` a symlink can lead back to one of them, which would
+    /// otherwise be copied forever.
+    ancestors: HashSet<(u64, u64)>,
+    /// Directories in the order they were made.
+    dirs: Vec<DirJob>,
+    /// The (dev, inode) of the files with several names met so far, under
+    /// `--preserve=links`.
+    linked: HashSet<(u64, u64)>,
+    /// Later names of those files, to be linked to the first copy.
+    links: Vec<FileJob>,
+}
+
+impl Walk<'_> {
+    fn fail(&self, path: &Path, error: io::Error) {
+        self.errors.lock().unwrap().push(CopyError { path: path.to_path_buf(), error });
+    }
+
+    fn dir(&mut self, src: &Path, dst: &Path, metadata: fs::Metadata, dispatch: &mut dyn FnMut(FileJob)) {
+        if trap::interrupted() {
+            return;
+        }
+        let key = (metadata.dev(), metadata.ino());
+        if !self.ancestors.insert(key) {
+            self.fail(src, io::Error::other("not copying directory loop"));
+            return;
+        }
+
+        let mode = metadata.permissions().mode() & 0o7777;
+        // The owner needs to be able to write into it until it is done.
+        let created = match fs::DirBuilder::new().mode(mode | 0o700).create(dst) {
+            Ok(()) => true,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && dst.is_dir() => false,
+            Err(e) => {
+                self.fail(src, e);
+                self.ancestors.remove(&key);
+                return;
+            }
+        };
+        report(src, dst, self.options);
+
+        match fs::read_dir(src) {
+            Ok(entries) => {
+                for entry in entries {
+                    if trap::interrupted() {
+                        break;
+                    }
+                    let entry = match entry {
+                        Ok(entry) => entry,
+                        Err(e) => {
+                            self.fail(src, e);
+                            continue;
+                        }
+                    };
+                    let path = entry.path();
+                    let dst_path = dst.join(entry.file_name());
+                    match self.options.metadata(&path, false) {
+                        Ok(entry_metadata) if entry_metadata.is_dir() => {
+                            self.dir(&path, &dst_path, entry_metadata, dispatch);
+                        }
+                        Ok(entry_metadata) => {
+                            let seen = self.options.preserve.links
+                                && entry_metadata.nlink() >= 2
+                                && !self.linked.insert((entry_metadata.dev(), entry_metadata.ino()));
+                            let job = FileJob { src: path, dst: dst_path, metadata: entry_metadata };
+                            // The first copy may still be in the works, so
+                            // later names wait until the workers are done.
+                            if seen { self.links.push(job) } else { dispatch(job) }
+                        }
+                        Err(e) => self.fail(&path, e),
+                    }
+                }
+            }
+            Err(e) => self.fail(src, e),
+        }
+
+        self.ancestors.remove(&key);
+        self.dirs.push(DirJob { src: src.to_path_buf(), dst: dst.to_path_buf(), metadata, created });
+    }
+}
+
+fn finish_dir(dir: &DirJob, options: &CopyOptions) -> io::Result<()> {
+    let mode = dir.metadata.permissions().mode() & 0o7777;
+    if options.preserve.any() {
+        apply_attributes(&dir.src, &dir.dst, &dir.metadata, options.preserve)?;
+    }
+    // The directory was made writable by its owner so it could be filled;
+    // unless the mode is preserved anyway, take that back now.
+    if dir.created && mode & 0o700 != 0o700 && !options.preserve.mode {
+        fs::set_permissions(&dir.dst, fs::Permissions::from_mode(mode & !current_umask()))?;
+    }
+    Ok(())
+}
+
+/// Gives `dst` the attributes of `src` picked by `preserve`. Ownership
+/// goes first, since changing it clears the set-id bits, and times last.
+fn apply_attributes(src: &Path, dst: &Path, metadata: &fs::Metadata, preserve: Preserve) -> io::Result<()> {
+    let c_dst = CString::new(dst.as_os_str().as_bytes())
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+
+    if preserve.ownership {
+        let rc = unsafe { libc::lchown(c_dst.as_ptr(), metadata.uid(), metadata.gid()) };
+        if rc != 0 {
+            // Only root may give files away; like GNU cp, the copy then
+            // belongs to whoever made it.
+            let error = io::Error::last_os_error();
+            if error.raw_os_error() != Some(libc::EPERM) {
+                return Err(error);
+            }
+        }
+    }
+    if preserve.xattr {
+        for name in xattr::list(src, false)? {
+            if let Some(value) = xattr::get(src, &name, false)? {
+                xattr::set(dst, &name, &value, false)?;
+            }
+        }
+    }
+    // After the xattrs, so that an ACL copied with them cannot leave the
+    // group bits different from the source's. A symlink has no mode of
+    // its own.
+    if preserve.mode && !metadata.file_type().is_symlink() {
+        fs::set_permissions(dst, fs::Permissions::from_mode(metadata.permissions().mode() & 0o7777))?;
+    }
+    if preserve.timestamps {
+        let times = [
+            libc::timespec { tv_sec: metadata.atime(), tv_nsec: metadata.atime_nsec() },
+            libc::timespec { tv_sec: metadata.mtime(), tv_nsec: metadata.mtime_nsec() },
+        ];
+        let rc = unsafe {
+            libc::utimensat(libc::AT_FDCWD, c_dst.as_ptr(), times.as_ptr(), libc::AT_SYMLINK_NOFOLLOW)
+        };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn current_umask() -> u32 {
+    // umask can only be read by setting it.
+    unsafe {
+        let mask = libc::umask(0);
+        libc::umask(mask);
+        mask as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::overwrite::Clobber;
+
+    /// A fresh directory under the system temp directory for one test.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("0-shell-copy-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn skipped_first_name_is_not_linked_to() {
+        let dir = scratch("skipped-link");
+        let (a, b, dst) = (dir.join("a"), dir.join("b"), dir.join("d"));
+        fs::write(&a, "NEW").unwrap();
+        fs::hard_link(&a, &b).unwrap();
+        fs::create_dir(&dst).unwrap();
+        fs::write(dst.join("a"), "OLD").unwrap();
+
+        let mut options = CopyOptions::new();
+        options.preserve.links = true;
+        options.overwrite.clobber = Clobber::Never;
+        for src in [&a, &b] {
+            copy_file(src, &dst.join(src.file_name().unwrap()), &fs::metadata(src).unwrap(), &options).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(dst.join("a")).unwrap(), "OLD");
+        assert_eq!(fs::read_to_string(dst.join("b")).unwrap(), "NEW");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn later_names_link_to_the_first_copy() {
+        let dir = scratch("links");
+        let (src, dst) = (dir.join("src"), dir.join("dst"));
+        fs::create_dir(&src).unwrap();
+        fs::write(src.join("a"), "data").unwrap();
+        fs::hard_link(src.join("a"), src.join("b")).unwrap();
+
+        let mut options = CopyOptions::new();
+        options.recursive = true;
+        options.preserve.links = true;
+        assert!(copy_dir(&src, &dst, &options).is_empty());
+
+        let (a, b) = (fs::metadata(dst.join("a")).unwrap(), fs::metadata(dst.join("b")).unwrap());
+        assert_eq!((a.dev(), a.ino()), (b.dev(), b.ino()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,15 +1,35 @@
 use crate::commands::CommandExecutor;
+use crate::commands::collation::{ Collation, CollationKey };
+use crate::commands::colors::ColorTable;
+use crate::commands::copy::{ self, CopyOptions, Dereference, Preserve, Reflink, Sparse };
+use crate::commands::columns::{ self, Cell, Layout };
+use crate::commands::overwrite::Clobber;
+use crate::commands::predicates;
+use crate::commands::progress::Progress;
+use crate::commands::quoting::{ self, QuotingStyle };
+use crate::commands::xattr::{ self, AclEntry, AclTag };
+use crate::encoding;
 use crate::error::ShellError;
+use crate::trap;
 use std::fs;
 use std::io::{ self, Read, Write };
-use std::path::Path;
+use std::cmp::Ordering;
+use std::cell::RefCell;
+use std::collections::{ HashMap, HashSet };
+use std::rc::Rc;
+use std::sync::Arc;
+use std::path::{ Path, PathBuf };
 use std::os::unix::fs::PermissionsExt;
 use std::os::unix::fs::FileTypeExt;
 use std::time::SystemTime;
 use std::os::unix::fs::MetadataExt;
-use std::ffi::CStr;
+use std::os::unix::io::FromRawFd;
+use std::ffi::{ CStr, OsStr, OsString };
+use std::os::unix::ffi::OsStrExt;
+use std::mem::ManuallyDrop;
 use std::env;
-use chrono::{ DateTime, Local };
+use chrono::{ DateTime, FixedOffset, SecondsFormat, Utc };
+use chrono::format::{ Item, StrftimeItems };
 
 pub struct PwdCommand;
 pub struct CdCommand;
@@ -19,12 +39,100 @@ pub struct MkdirCommand;
 pub struct CpCommand;
 pub struct MvCommand;
 pub struct RmCommand;
+pub struct GetfaclCommand;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct LsFlags {
     show_hidden: bool,
     long_format: bool,
     file_indicators: bool,
+    recursive: bool,
+    sort: SortKey,
+    reverse: bool,
+    group_directories_first: bool,
+    /// Name order, from `LC_ALL`/`LC_COLLATE`/`LANG`.
+    collation: Collation,
+    /// `-h` (1024) or `--si` (1000): sizes with K/M/G suffixes.
+    human_base: Option<u64>,
+    show_inode: bool,
+    show_blocks: bool,
+    numeric_ids: bool,
+    layout: Layout,
+    /// Set by `--color` when names are to be coloured.
+    colors: Option<ColorTable>,
+    /// `-L`: show what every symlink points to instead of the link.
+    dereference: bool,
+    /// `-H`: follow symlinks named on the command line only.
+    dereference_args: bool,
+    /// `-d`: list directories themselves, not their contents.
+    directory: bool,
+    time_field: TimeField,
+    time_style: TimeStyle,
+    json: Option<JsonMode>,
+    quoting: QuotingStyle,
+    /// `-@`: each attribute under its entry in long format.
+    show_xattrs: bool,
+    /// Shared with the copies made for command-line arguments.
+    names: Rc<NameCache>,
+}
+
+/// uid and gid names looked up during one `ls` run. A directory's entries
+/// usually belong to a handful of users, so each id goes to NSS once.
+#[derive(Debug, Default)]
+struct NameCache {
+    users: RefCell<HashMap<u32, String>>,
+    groups: RefCell<HashMap<u32, String>>,
+}
+
+/// Upper bound for the `getpwuid_r`/`getgrgid_r` buffer.
+const MAX_NSS_BUFFER: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum JsonMode {
+    /// `--json`: one array holding every entry.
+    Array,
+    /// `--ndjson`: one object per line, for streaming.
+    Lines,
+}
+
+/// Which timestamp `-l` shows and `-t` sorts by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TimeField {
+    Modified,
+    /// `-u`
+    Accessed,
+    /// `-c`: inode change time.
+    Changed,
+    /// `--time=birth`: not every filesystem records it.
+    Birth,
+}
+
+/// `--time-style`. The custom formats are chrono strftime strings for files
+/// from the last six months and for older ones.
+#[derive(Debug, Clone, PartialEq)]
+enum TimeStyle {
+    Locale,
+    FullIso,
+    LongIso,
+    Iso,
+    Custom { recent: String, old: String },
+}
+
+/// Order of directory entries; the last of `-t`, `-S`, `-X`, `-v` and `-U`
+/// on the command line wins.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortKey {
+    Name,
+    /// `-t`: newest first.
+    Time,
+    /// `-S`: largest first.
+    Size,
+    /// `-X`: by the text after the last `.`.
+    Extension,
+    /// `-v`: numbers inside names compare by value, so `file2` < `file10`.
+    Version,
+    /// `-U`: directory order.
+    Unsorted,
 }
 
 pub struct CommandOptions {
@@ -32,7 +140,7 @@ pub struct CommandOptions {
 }
 
 impl CommandExecutor for PwdCommand {
-    fn execute(&self, args: &[String]) -> Result<(), ShellError> {
+    fn execute(&self, args: &[OsString]) -> Result<(), ShellError> {
         let mut is_option = true;
 
         for arg in args {
@@ -40,8 +148,8 @@ impl CommandExecutor for PwdCommand {
                 is_option = false;
                 continue;
             }
-            if arg.starts_with('-') && is_option {
-                return Err(ShellError::InvalidOption(arg.clone()));
+            if arg.as_bytes().starts_with(b"-") && is_option {
+                return Err(ShellError::InvalidOption(arg.to_string_lossy().into_owned()));
             }
         }
 
@@ -55,17 +163,17 @@ impl CommandExecutor for PwdCommand {
 }
 
 impl CommandExecutor for CdCommand {
-    fn execute(&self, args: &[String]) -> Result<(), ShellError> {
+    fn execute(&self, args: &[OsString]) -> Result<(), ShellError> {
         let mut is_option = true;
-        let mut target_dir = String::new();
+        let mut target_dir = OsString::new();
 
         for arg in args {
             if arg == "--" {
                 is_option = false;
                 continue;
             }
-            if arg.starts_with('-') && is_option && arg != "-" {
-                return Err(ShellError::InvalidOption(arg.clone()));
+            if arg.as_bytes().starts_with(b"-") && is_option && arg != "-" {
+                return Err(ShellError::InvalidOption(arg.to_string_lossy().into_owned()));
             }
             if target_dir.is_empty() {
                 target_dir = arg.clone();
@@ -73,8 +181,8 @@ impl CommandExecutor for CdCommand {
         }
 
         let current_dir = std::env::current_dir()
-            .map(|p| p.to_string_lossy().to_string())
-            .unwrap_or_else(|_| ".".to_string());
+            .map(PathBuf::into_os_string)
+            .unwrap_or_else(|_| OsString::from("."));
 
         let target_dir = self.resolve_target_directory(&target_dir)?;
 
@@ -89,22 +197,22 @@ impl CommandExecutor for CdCommand {
                 match e.kind() {
                     io::ErrorKind::NotFound => {
                         Err(ShellError::FileSystemError(
-                            format!("cd: {}: No such file or directory", target_dir)
+                            format!("cd: {}: No such file or directory", target_dir.display())
                         ))
                     }
                     io::ErrorKind::PermissionDenied => {
                         Err(ShellError::FileSystemError(
-                            format!("cd: {}: Permission denied", target_dir)
+                            format!("cd: {}: Permission denied", target_dir.display())
                         ))
                     }
                     io::ErrorKind::NotADirectory => {
                         Err(ShellError::FileSystemError(
-                            format!("cd: {}: Not a directory", target_dir)
+                            format!("cd: {}: Not a directory", target_dir.display())
                         ))
                     }
                     _ => {
                         Err(ShellError::FileSystemError(
-                            format!("cd: {}: {}", target_dir, e)
+                            format!("cd: {}: {}", target_dir.display(), e)
                         ))
                     }
                 }
@@ -118,86 +226,88 @@ impl CommandExecutor for CdCommand {
 }
 
 impl CdCommand {
-    fn resolve_target_directory(&self, target: &str) -> Result<String, ShellError> {
+    fn resolve_target_directory(&self, target: &OsStr) -> Result<PathBuf, ShellError> {
         if target.is_empty() {
-            return Ok(self.get_home_directory()?);
+            return self.get_home_directory();
         }
 
-        if target.starts_with("~/") {
+        if let Some(path) = target.as_bytes().strip_prefix(b"~/") {
             let home = self.get_home_directory()?;
-            let path = target.strip_prefix("~/").unwrap();
-            return Ok(format!("{}/{}", home, path));
+            return Ok(home.join(OsStr::from_bytes(path)));
         }
 
         if target == "-" {
             return self.get_oldpwd_directory();
         }
 
-        Ok(target.to_string())
+        Ok(PathBuf::from(target))
     }
 
-    fn get_home_directory(&self) -> Result<String, ShellError> {
-        std::env::var("HOME")
-            .map_err(|_| ShellError::FileSystemError(
+    fn get_home_directory(&self) -> Result<PathBuf, ShellError> {
+        std::env::var_os("HOME")
+            .map(PathBuf::from)
+            .ok_or_else(|| ShellError::FileSystemError(
                 "cd: HOME environment variable not set".to_string()
             ))
     }
 
-    fn get_oldpwd_directory(&self) -> Result<String, ShellError> {
-        let oldpwd = std::env::var("OLDPWD")
-            .map_err(|_| ShellError::FileSystemError(
+    fn get_oldpwd_directory(&self) -> Result<PathBuf, ShellError> {
+        let oldpwd = std::env::var_os("OLDPWD")
+            .ok_or_else(|| ShellError::FileSystemError(
                 "cd: OLDPWD not set".to_string()
             ))?;
-        
-        println!("{}", oldpwd);
-        Ok(oldpwd)
+
+        let mut out = io::stdout().lock();
+        out.write_all(oldpwd.as_bytes()).and_then(|_| out.write_all(b"\n"))?;
+        Ok(PathBuf::from(oldpwd))
     }
 }
 
 impl CommandExecutor for LsCommand {
-    fn execute(&self, args: &[String]) -> Result<(), ShellError> {
-        for _arg in args {}
+    fn execute(&self, args: &[OsString]) -> Result<(), ShellError> {
         let (flags, paths) = self.parse_args(args)?;
-        
-        
+
         let paths = if paths.is_empty() {
-            vec![".".to_string()]
+            vec![OsString::from(".")]
         } else {
             paths
         };
 
+        // Like GNU ls, symlinks named on the command line are followed
+        // unless the listing is about the links themselves.
+        let arg_flags = LsFlags {
+            dereference: flags.dereference
+                || flags.dereference_args
+                || !(flags.long_format || flags.file_indicators || flags.directory || flags.json.is_some()),
+            ..flags.clone()
+        };
+
         let mut files = Vec::new();
         let mut directories = Vec::new();
-        let mut missing = Vec::new();
-        
+
         for path in &paths {
-            let path_obj = Path::new(path);
-            if path_obj.is_file() {
-                files.push(path.clone());
-            } else if path_obj.is_dir() {
-                directories.push(path.clone());
-            } else {
-                if !path_obj.exists() {
-                    missing.push(path.clone());
-                } else {
-                    files.push(path.clone());
-                }
+            // A broken symlink is still there to be listed, so existence
+            // is checked without following it.
+            match self.entry_metadata(Path::new(path), &arg_flags) {
+                Ok(meta) if meta.is_dir() && !flags.directory => directories.push(path.clone()),
+                Ok(_) => files.push(path.clone()),
+                Err(e) => eprintln!("ls: {}: {}", Path::new(path).display(), describe_io_error(&e)),
             }
         }
-        
-        for m in &missing {
-            eprintln!("ls: {}: No such file or directory", m);
+
+        if let Some(mode) = flags.json {
+            return self.print_json(&files, &directories, mode, &flags, &arg_flags);
         }
-        
+
         if !files.is_empty() {
             if flags.long_format {
                 for file in &files {
-                    if let Err(e) = self.list_file(Path::new(file), &flags) {
-                        eprintln!("ls: {}: {}", file, e);
+                    if let Err(e) = self.list_file(Path::new(file), &arg_flags) {
+                        eprintln!("ls: {}: {}", Path::new(file).display(), e);
                     }
                 }
             } else {
-                if let Err(e) = self.list_files_together(&files, &flags) {
+                if let Err(e) = self.list_files_together(&files, &arg_flags) {
                     eprintln!("ls: error listing files: {}", e);
                 }
             }
@@ -207,11 +317,15 @@ impl CommandExecutor for LsCommand {
             if !files.is_empty() || i > 0 {
                 println!();
             }
+            if flags.recursive {
+                self.list_recursive(Path::new(dir), &flags, &mut HashSet::new(), false)?;
+                continue;
+            }
             if !files.is_empty() || directories.len() > 1 {
-                println!("{}:", dir);
+                println!("{}:", quoting::quote(dir, flags.quoting));
             }
             if let Err(e) = self.list_directory_contents(Path::new(dir), &flags) {
-                eprintln!("ls: {}: {}", dir, e);
+                eprintln!("ls: {}: {}", Path::new(dir).display(), e);
             }
         }
 
@@ -219,7 +333,7 @@ impl CommandExecutor for LsCommand {
     }
 
     fn help(&self) -> &str {
-        "Usage: ls [-a] [-l] [-F] [directory...] - List directory contents"
+        "Usage: ls [-alFRrtSXvUhisnCx1mLHducbQNq@] [--group-directories-first] [--si] [--color[=WHEN]] [--time-style=STYLE] [--full-time] [--quoting-style=STYLE] [--xattr] [--json|--ndjson] [directory...] - List directory contents"
     }
 }
 
@@ -240,34 +354,101 @@ impl LsCommand {
             }
         }
         for entry in files {
-            if let Ok(meta) = fs::symlink_metadata(entry.path()) {
+            if let Ok(meta) = self.entry_metadata(&entry.path(), flags) {
                 blocks = blocks.saturating_add(meta.blocks());
             }
         }
-        let total_k = (blocks + 1) / 2;
-        println!("total {}", total_k);
+        println!("total {}", self.format_blocks(blocks, flags));
         Ok(())
     }
 
-    fn print_one_long(&self, dir: &Path, name: &str, flags: &LsFlags) -> Result<(), ShellError> {
+    /// Formats a count of 512-byte blocks as GNU ls does: 1K blocks, or a
+    /// byte size under `-h`/`--si`.
+    fn format_blocks(&self, blocks: u64, flags: &LsFlags) -> String {
+        match flags.human_base {
+            Some(base) => human_size(blocks.saturating_mul(512), base),
+            None => blocks.div_ceil(2).to_string(),
+        }
+    }
+
+    fn format_size(&self, bytes: u64, flags: &LsFlags) -> String {
+        match flags.human_base {
+            Some(base) => human_size(bytes, base),
+            None => bytes.to_string(),
+        }
+    }
+
+    /// The `-i` and `-s` columns that go before an entry.
+    fn index_columns(&self, meta: &fs::Metadata, flags: &LsFlags) -> (String, String) {
+        let inode = if flags.show_inode { meta.ino().to_string() } else { String::new() };
+        let blocks = if flags.show_blocks { self.format_blocks(meta.blocks(), flags) } else { String::new() };
+        (inode, blocks)
+    }
+
+    fn format_index_columns(
+        &self,
+        inode: &str,
+        blocks: &str,
+        flags: &LsFlags,
+        inode_width: usize,
+        blocks_width: usize
+    ) -> String {
+        let mut out = String::new();
+        if flags.show_inode {
+            out.push_str(&format!("{:>width$} ", inode, width = inode_width));
+        }
+        if flags.show_blocks {
+            out.push_str(&format!("{:>width$} ", blocks, width = blocks_width));
+        }
+        out
+    }
+
+    fn owner_field(&self, uid: u32, flags: &LsFlags) -> String {
+        if flags.numeric_ids { uid.to_string() } else { self.get_owner_name(uid, flags) }
+    }
+
+    fn group_field(&self, gid: u32, flags: &LsFlags) -> String {
+        if flags.numeric_ids { gid.to_string() } else { self.get_group_name(gid, flags) }
+    }
+
+    /// lstat, or stat under `-L`. A broken link falls back to the link.
+    fn entry_metadata(&self, path: &Path, flags: &LsFlags) -> io::Result<fs::Metadata> {
+        if flags.dereference {
+            fs::metadata(path).or_else(|_| fs::symlink_metadata(path))
+        } else {
+            fs::symlink_metadata(path)
+        }
+    }
+
+    /// `name` quoted by `flags.quoting` and coloured for the file at `path`
+    /// when `--color` is on.
+    fn paint(&self, name: &OsStr, path: &Path, metadata: Option<&fs::Metadata>, flags: &LsFlags) -> String {
+        let text = quoting::quote(name, flags.quoting);
+        match &flags.colors {
+            Some(colors) => colors.paint(&name.to_string_lossy(), &text, path, metadata),
+            None => text,
+        }
+    }
+
+    fn print_one_long(&self, dir: &Path, name: &OsStr, flags: &LsFlags) -> Result<(), ShellError> {
         let full = dir.join(name);
-        let meta = fs
-            ::symlink_metadata(&full)
-            .map_err(|e| ShellError::FileSystemError(e.to_string()))?;
-        let perms = self.format_permissions_with_extended(&meta, &full);
+        let meta = self
+            .entry_metadata(&full, flags)
+            .map_err(|e| ShellError::FileSystemError(describe_io_error(&e)))?;
+        let perms = self.format_permissions_with_extended(&meta, &full, flags);
+        let (inode, blocks) = self.index_columns(&meta, flags);
         let nlink = meta.nlink();
-        let owner = self.get_owner_name(meta.uid());
-        let group = self.get_group_name(meta.gid());
+        let owner = self.owner_field(meta.uid(), flags);
+        let group = self.group_field(meta.gid(), flags);
         let ftype = meta.file_type();
         let size_field = if ftype.is_char_device() || ftype.is_block_device() {
             let (maj, min) = self.major_minor(meta.rdev());
             format!("{:>3}, {:>5}", maj, min)
         } else {
-            format!("{:>8}", meta.len())
+            format!("{:>8}", self.format_size(meta.len(), flags))
         };
-        let time_to_show = meta.modified().unwrap_or_else(|_| SystemTime::now());
-        let time_str = self.format_time(time_to_show);
-        let mut display_name = name.to_string();
+        let time_str = self.format_time(self.file_time(&meta, flags), flags);
+        let mut display_name = self.paint(name, &full, Some(&meta), flags);
         if flags.file_indicators {
             if ftype.is_dir() {
                 display_name.push('/');
@@ -279,26 +460,27 @@ impl LsCommand {
                 display_name.push('|');
             } else if ftype.is_socket() {
                 display_name.push('=');
-            } else if !ftype.is_symlink() && self.is_executable(&meta) {
+            } else if !ftype.is_symlink() && predicates::is_executable(&meta) {
                 display_name.push('*');
             }
         }
         let link_suffix = if ftype.is_symlink() {
             match fs::read_link(&full) {
                 Ok(t) => {
-                    let mut target = t.display().to_string();
-                    if flags.file_indicators {
-                        if let Ok(target_meta) = fs::metadata(&full) {
-                            let target_type = target_meta.file_type();
-                            if target_type.is_socket() {
-                                target.push('=');
-                            } else if target_type.is_dir() {
-                                target.push('/');
-                            } else if target_type.is_fifo() {
-                                target.push('|');
-                            } else if self.is_executable(&target_meta) {
-                                target.push('*');
-                            }
+                    let target_meta = fs::metadata(&full);
+                    let mut target = self.paint(t.as_os_str(), &full, target_meta.as_ref().ok(), flags);
+                    if flags.file_indicators
+                        && let Ok(target_meta) = target_meta
+                    {
+                        let target_type = target_meta.file_type();
+                        if target_type.is_socket() {
+                            target.push('=');
+                        } else if target_type.is_dir() {
+                            target.push('/');
+                        } else if target_type.is_fifo() {
+                            target.push('|');
+                        } else if predicates::is_executable(&target_meta) {
+                            target.push('*');
                         }
                     }
                     format!(" -> {}", target)
@@ -316,7 +498,8 @@ impl LsCommand {
         let size_width = size_field.len().max(1);
 
         println!(
-            "{:<perms_width$} {:>nlink_width$} {:<owner_width$} {:<group_width$} {:>size_width$} {} {}{}",
+            "{}{:<perms_width$} {:>nlink_width$} {:<owner_width$} {:<group_width$} {:>size_width$} {} {}{}",
+            self.format_index_columns(&inode, &blocks, flags, inode.len(), blocks.len()),
             perms,
             nlink,
             owner,
@@ -331,31 +514,94 @@ impl LsCommand {
             group_width = group_width,
             size_width = size_width
         );
+        if flags.show_xattrs {
+            self.print_xattrs(&full, flags);
+        }
         Ok(())
     }
+
+    /// The `-@` lines: every attribute with its value, ACLs written out
+    /// entry by entry.
+    fn print_xattrs(&self, path: &Path, flags: &LsFlags) {
+        let names = match xattr::list(path, flags.dereference) {
+            Ok(names) => names,
+            Err(e) => {
+                eprintln!("ls: {}: {}", path.display(), describe_io_error(&e));
+                return;
+            }
+        };
+        for name in names {
+            let value = match xattr::get(path, &name, flags.dereference) {
+                Ok(Some(value)) => value,
+                Ok(None) => continue,
+                Err(e) => {
+                    eprintln!("ls: {}: {}: {}", path.display(), name.display(), describe_io_error(&e));
+                    continue;
+                }
+            };
+            let is_acl = name == xattr::ACL_ACCESS || name == xattr::ACL_DEFAULT;
+            let shown = match xattr::parse_acl(&value) {
+                Some(acl) if is_acl => acl
+                    .iter()
+                    .map(|entry| {
+                        acl_entry_text(entry, |uid| self.owner_field(uid, flags), |gid| self.group_field(gid, flags))
+                    })
+                    .collect::<Vec<_>>()
+                    .join(","),
+                _ => xattr::format_value(&value),
+            };
+            println!("\t{}={}", quoting::quote(&name, flags.quoting), shown);
+        }
+    }
     fn major_minor(&self, rdev: u64) -> (u32, u32) {
         let major = ((rdev >> 8) & 0xfff) as u32;
         let minor = ((rdev & 0xff) | ((rdev >> 12) & 0xfffff00)) as u32;
         (major, minor)
     }
-    fn get_owner_name(&self, uid: u32) -> String {
-        unsafe {
-            let passwd = libc::getpwuid(uid as libc::uid_t);
-            if !passwd.is_null() {
-                let name = CStr::from_ptr((*passwd).pw_name).to_string_lossy();
-                return name.to_string();
+    fn get_owner_name(&self, uid: u32, flags: &LsFlags) -> String {
+        if let Some(name) = flags.names.users.borrow().get(&uid) {
+            return name.clone();
+        }
+        let name = self.lookup_owner_name(uid);
+        flags.names.users.borrow_mut().insert(uid, name.clone());
+        name
+    }
+
+    fn get_group_name(&self, gid: u32, flags: &LsFlags) -> String {
+        if let Some(name) = flags.names.groups.borrow().get(&gid) {
+            return name.clone();
+        }
+        let name = self.lookup_group_name(gid);
+        flags.names.groups.borrow_mut().insert(gid, name.clone());
+        name
+    }
+
+    pub(super) fn lookup_owner_name(&self, uid: u32) -> String {
+        let mut buffer: Vec<libc::c_char> = vec![0; 1024];
+        loop {
+            let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+            let mut result: *mut libc::passwd = std::ptr::null_mut();
+            let rc = unsafe {
+                libc::getpwuid_r(uid, &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result)
+            };
+            if rc == libc::ERANGE && buffer.len() < MAX_NSS_BUFFER {
+                buffer.resize(buffer.len() * 2, 0);
+                continue;
+            }
+            if rc == 0 && !result.is_null() {
+                return unsafe { CStr::from_ptr(passwd.pw_name) }.to_string_lossy().into_owned();
             }
+            break;
         }
 
         if let Ok(content) = std::fs::read_to_string("/etc/passwd") {
             for line in content.lines() {
                 let parts: Vec<&str> = line.split(':').collect();
-                if parts.len() >= 3 {
-                    if let Ok(line_uid) = parts[2].parse::<u32>() {
-                        if line_uid == uid {
-                            return parts[0].to_string();
-                        }
-                    }
+                if parts.len() >= 3
+                    && let Ok(line_uid) = parts[2].parse::<u32>()
+                    && line_uid == uid
+                {
+                    return parts[0].to_string();
                 }
             }
         }
@@ -363,24 +609,33 @@ impl LsCommand {
         uid.to_string()
     }
 
-    fn get_group_name(&self, gid: u32) -> String {
-        unsafe {
-            let group = libc::getgrgid(gid as libc::gid_t);
-            if !group.is_null() {
-                let name = CStr::from_ptr((*group).gr_name).to_string_lossy();
-                return name.to_string();
+    pub(super) fn lookup_group_name(&self, gid: u32) -> String {
+        let mut buffer: Vec<libc::c_char> = vec![0; 1024];
+        loop {
+            let mut group: libc::group = unsafe { std::mem::zeroed() };
+            let mut result: *mut libc::group = std::ptr::null_mut();
+            let rc = unsafe {
+                libc::getgrgid_r(gid, &mut group, buffer.as_mut_ptr(), buffer.len(), &mut result)
+            };
+            // Groups with many members need a bigger buffer.
+            if rc == libc::ERANGE && buffer.len() < MAX_NSS_BUFFER {
+                buffer.resize(buffer.len() * 2, 0);
+                continue;
+            }
+            if rc == 0 && !result.is_null() {
+                return unsafe { CStr::from_ptr(group.gr_name) }.to_string_lossy().into_owned();
             }
+            break;
         }
 
         if let Ok(content) = std::fs::read_to_string("/etc/group") {
             for line in content.lines() {
                 let parts: Vec<&str> = line.split(':').collect();
-                if parts.len() >= 3 {
-                    if let Ok(line_gid) = parts[2].parse::<u32>() {
-                        if line_gid == gid {
-                            return parts[0].to_string();
-                        }
-                    }
+                if parts.len() >= 3
+                    && let Ok(line_gid) = parts[2].parse::<u32>()
+                    && line_gid == gid
+                {
+                    return parts[0].to_string();
                 }
             }
         }
@@ -388,23 +643,111 @@ impl LsCommand {
         gid.to_string()
     }
 
-    fn parse_args(&self, args: &[String]) -> Result<(LsFlags, Vec<String>), ShellError> {
+    fn parse_args(&self, args: &[OsString]) -> Result<(LsFlags, Vec<OsString>), ShellError> {
         let mut flags = LsFlags {
             show_hidden: false,
             long_format: false,
             file_indicators: false,
+            recursive: false,
+            sort: SortKey::Name,
+            reverse: false,
+            group_directories_first: false,
+            collation: Collation::from_env(),
+            human_base: None,
+            show_inode: false,
+            show_blocks: false,
+            numeric_ids: false,
+            layout: Layout::default_for_stdout(),
+            colors: None,
+            dereference: false,
+            dereference_args: false,
+            directory: false,
+            time_field: TimeField::Modified,
+            time_style: TimeStyle::Locale,
+            json: None,
+            quoting: default_quoting_style(),
+            show_xattrs: false,
+            names: Rc::new(NameCache::default()),
         };
 
         let mut is_option = true;
         let mut paths = Vec::new();
 
-        for arg in args {
+        for os_arg in args {
+            // Options are always text; a name that is not UTF-8 is a path.
+            let arg = match os_arg.to_str() {
+                Some(arg) => arg,
+                None => {
+                    paths.push(os_arg.clone());
+                    continue;
+                }
+            };
             if arg.starts_with('-') && is_option {
                 if arg == "-" {
-                    paths.push(arg.clone());
+                    paths.push(os_arg.clone());
                 } else if arg == "--" {
                     is_option = false;
                     continue;
+                } else if arg == "--group-directories-first" {
+                    flags.group_directories_first = true;
+                } else if arg == "--si" {
+                    flags.human_base = Some(1000);
+                } else if arg == "--human-readable" {
+                    flags.human_base = Some(1024);
+                } else if arg == "--json" {
+                    flags.json = Some(JsonMode::Array);
+                } else if arg == "--ndjson" {
+                    flags.json = Some(JsonMode::Lines);
+                } else if arg == "--full-time" {
+                    flags.long_format = true;
+                    flags.time_style = TimeStyle::FullIso;
+                } else if let Some(word) = arg.strip_prefix("--quoting-style=") {
+                    flags.quoting = QuotingStyle::parse(word).ok_or_else(|| {
+                        ShellError::ExecutionError(format!("ls: invalid argument '{}' for '--quoting-style'", word))
+                    })?;
+                } else if arg == "--xattr" {
+                    flags.show_xattrs = true;
+                } else if arg == "--escape" {
+                    flags.quoting = QuotingStyle::Escape;
+                } else if arg == "--quote-name" {
+                    flags.quoting = QuotingStyle::C;
+                } else if arg == "--literal" || arg == "--hide-control-chars" {
+                    flags.quoting = QuotingStyle::Literal;
+                } else if let Some(style) = arg.strip_prefix("--time-style=") {
+                    flags.time_style = self.parse_time_style(style)?;
+                } else if let Some(word) = arg.strip_prefix("--time=") {
+                    flags.time_field = match word {
+                        "atime" | "access" | "use" => TimeField::Accessed,
+                        "ctime" | "status" => TimeField::Changed,
+                        "birth" | "creation" => TimeField::Birth,
+                        "mtime" | "modification" => TimeField::Modified,
+                        _ => {
+                            return Err(
+                                ShellError::ExecutionError(
+                                    format!("ls: invalid argument '{}' for '--time'", word)
+                                )
+                            );
+                        }
+                    };
+                } else if arg == "--color" || arg.starts_with("--color=") {
+                    let when = arg.strip_prefix("--color=").unwrap_or("always");
+                    let enabled = match when {
+                        "always" | "yes" | "force" => true,
+                        "never" | "no" | "none" => false,
+                        "auto" | "tty" | "if-tty" => unsafe { libc::isatty(libc::STDOUT_FILENO) == 1 },
+                        _ => {
+                            return Err(
+                                ShellError::ExecutionError(
+                                    format!("ls: invalid argument '{}' for '--color'", when)
+                                )
+                            );
+                        }
+                    };
+                    flags.colors = if enabled { Some(ColorTable::from_env()) } else { None };
+                } else if arg.starts_with("--") {
+                    return Err(
+                        ShellError::ExecutionError(format!("ls: unrecognized option '{}'", arg))
+                    );
                 } else {
                     for c in arg[1..].chars() {
                         match c {
@@ -417,6 +760,78 @@ impl LsCommand {
                             'F' => {
                                 flags.file_indicators = true;
                             }
+                            'R' => {
+                                flags.recursive = true;
+                            }
+                            'r' => {
+                                flags.reverse = true;
+                            }
+                            't' => {
+                                flags.sort = SortKey::Time;
+                            }
+                            'S' => {
+                                flags.sort = SortKey::Size;
+                            }
+                            'X' => {
+                                flags.sort = SortKey::Extension;
+                            }
+                            'v' => {
+                                flags.sort = SortKey::Version;
+                            }
+                            'U' => {
+                                flags.sort = SortKey::Unsorted;
+                            }
+                            'h' => {
+                                flags.human_base = Some(1024);
+                            }
+                            'i' => {
+                                flags.show_inode = true;
+                            }
+                            's' => {
+                                flags.show_blocks = true;
+                            }
+                            'n' => {
+                                flags.long_format = true;
+                                flags.numeric_ids = true;
+                            }
+                            'C' | 'x' | 'm' => {
+                                flags.long_format = false;
+                                flags.layout = match c {
+                                    'C' => Layout::Columns,
+                                    'x' => Layout::Across,
+                                    _ => Layout::Commas,
+                                };
+                            }
+                            '1' => {
+                                flags.layout = Layout::OneLine;
+                            }
+                            'L' => {
+                                flags.dereference = true;
+                            }
+                            'H' => {
+                                flags.dereference_args = true;
+                            }
+                            'd' => {
+                                flags.directory = true;
+                            }
+                            'u' => {
+                                flags.time_field = TimeField::Accessed;
+                            }
+                            'c' => {
+                                flags.time_field = TimeField::Changed;
+                            }
+                            'b' => {
+                                flags.quoting = QuotingStyle::Escape;
+                            }
+                            'Q' => {
+                                flags.quoting = QuotingStyle::C;
+                            }
+                            'N' | 'q' => {
+                                flags.quoting = QuotingStyle::Literal;
+                            }
+                            '@' => {
+                                flags.show_xattrs = true;
+                            }
                             _ => {
                                 return Err(
                                     ShellError::ExecutionError(
@@ -428,38 +843,208 @@ impl LsCommand {
                     }
                 }
             } else {
-                paths.push(arg.clone());
+                paths.push(os_arg.clone());
             }
         }
 
+        // Outside long format, `-u` and `-c` pick the time to sort by.
+        if !flags.long_format && flags.time_field != TimeField::Modified && flags.sort == SortKey::Name {
+            flags.sort = SortKey::Time;
+        }
+
+        // Read `TZ` again once for this listing, since it may have been
+        // set just for this command; `local_datetime` relies on it.
+        unsafe { tzset() };
+
         Ok((flags, paths))
     }
 
-    fn list_directory(&self, path_str: &str, flags: &LsFlags) -> Result<(), ShellError> {
-        let path = Path::new(path_str);
+    /// `--json`/`--ndjson`: the files named on the command line, then the
+    /// contents of each directory (recursively under `-R`).
+    fn print_json(
+        &self,
+        files: &[OsString],
+        directories: &[OsString],
+        mode: JsonMode,
+        flags: &LsFlags,
+        arg_flags: &LsFlags
+    ) -> Result<(), ShellError> {
+        let mut objects = Vec::new();
+        for file in files {
+            let path = Path::new(file);
+            match self.json_object(path.as_os_str(), path, arg_flags) {
+                Ok(object) => objects.push(object),
+                Err(e) => eprintln!("ls: {}: {}", path.display(), e),
+            }
+        }
+        for dir in directories {
+            self.collect_json(Path::new(dir), flags, &mut HashSet::new(), &mut objects)?;
+        }
+
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        match mode {
+            JsonMode::Lines => {
+                for object in &objects {
+                    writeln!(out, "{}", object)?;
+                }
+            }
+            JsonMode::Array if objects.is_empty() => writeln!(out, "[]")?,
+            JsonMode::Array => {
+                writeln!(out, "[")?;
+                for (i, object) in objects.iter().enumerate() {
+                    let comma = if i + 1 < objects.len() { "," } else { "" };
+                    writeln!(out, "  {}{}", object, comma)?;
+                }
+                writeln!(out, "]")?;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
 
-        if !path.exists() {
-            return Err(ShellError::FileSystemError("No such file or directory".to_string()));
+    fn collect_json(
+        &self,
+        path: &Path,
+        flags: &LsFlags,
+        ancestors: &mut HashSet<(u64, u64)>,
+        objects: &mut Vec<String>
+    ) -> Result<(), ShellError> {
+        // A large tree can take a while; a trapped signal stops it.
+        trap::check_interrupted()?;
+        let key = fs::metadata(path).map(|meta| (meta.dev(), meta.ino())).ok();
+        if let Some(key) = key
+            && !ancestors.insert(key)
+        {
+            eprintln!("ls: {}: not listing already-listed directory", path.display());
+            return Ok(());
         }
 
-        if path.is_file() {
-            return self.list_file(path, flags);
-        } else if path.is_dir() {
-            return self.list_directory_contents(path, flags);
+        match self.read_directory_entries(path, flags) {
+            Ok(entries) => {
+                for entry in &entries {
+                    match self.json_object(&entry.file_name(), &entry.path(), flags) {
+                        Ok(object) => objects.push(object),
+                        Err(e) => eprintln!("ls: {}: {}", entry.path().display(), e),
+                    }
+                }
+                if flags.recursive {
+                    for entry in &entries {
+                        if self.entry_metadata(&entry.path(), flags).is_ok_and(|m| m.is_dir()) {
+                            self.collect_json(&entry.path(), flags, ancestors, objects)?;
+                        }
+                    }
+                }
+            }
+            Err(e) => eprintln!("ls: {}: {}", path.display(), e),
+        }
+
+        if let Some(key) = key {
+            ancestors.remove(&key);
+        }
+        Ok(())
+    }
+
+    fn json_object(&self, name: &OsStr, path: &Path, flags: &LsFlags) -> Result<String, ShellError> {
+        let meta = self
+            .entry_metadata(path, flags)
+            .map_err(|e| ShellError::FileSystemError(describe_io_error(&e)))?;
+        let ftype = meta.file_type();
+        let kind = if ftype.is_symlink() {
+            "symlink"
+        } else if ftype.is_dir() {
+            "directory"
+        } else if ftype.is_fifo() {
+            "fifo"
+        } else if ftype.is_socket() {
+            "socket"
+        } else if ftype.is_block_device() {
+            "block_device"
+        } else if ftype.is_char_device() {
+            "char_device"
+        } else {
+            "file"
+        };
+        let target = if ftype.is_symlink() {
+            fs::read_link(path).map(|t| json_name(t.as_os_str())).unwrap_or_else(|_| "null".to_string())
         } else {
-            return self.list_file(path, flags);
+            "null".to_string()
+        };
+        let timestamp = |time: Option<SystemTime>| match time {
+            Some(time) => json_string(&local_datetime(time).to_rfc3339_opts(SecondsFormat::Nanos, false)),
+            None => "null".to_string(),
+        };
+        let ctime = SystemTime::UNIX_EPOCH + std::time::Duration::new(
+            meta.ctime().max(0) as u64,
+            meta.ctime_nsec().clamp(0, 999_999_999) as u32
+        );
+
+        let fields = [
+            ("name", json_name(name)),
+            ("path", json_name(path.as_os_str())),
+            ("type", json_string(kind)),
+            ("mode", json_string(&format!("{:04o}", meta.permissions().mode() & 0o7777))),
+            ("permissions", json_string(&self.format_permissions(&meta))),
+            ("nlink", meta.nlink().to_string()),
+            ("uid", meta.uid().to_string()),
+            ("gid", meta.gid().to_string()),
+            ("owner", json_string(&self.get_owner_name(meta.uid(), flags))),
+            ("group", json_string(&self.get_group_name(meta.gid(), flags))),
+            ("size", meta.len().to_string()),
+            ("blocks", meta.blocks().to_string()),
+            ("inode", meta.ino().to_string()),
+            ("mtime", timestamp(meta.modified().ok())),
+            ("atime", timestamp(meta.accessed().ok())),
+            ("ctime", timestamp(Some(ctime))),
+            ("birthtime", timestamp(meta.created().ok())),
+            ("target", target),
+            ("xattr", (!xattr::list(path, flags.dereference).unwrap_or_default().is_empty()).to_string()),
+        ];
+        let body = fields
+            .iter()
+            .map(|(key, value)| format!("\"{}\":{}", key, value))
+            .collect::<Vec<_>>()
+            .join(",");
+        Ok(format!("{{{}}}", body))
+    }
+
+    fn parse_time_style(&self, style: &str) -> Result<TimeStyle, ShellError> {
+        // `posix-` styles only differ from the plain ones in non-POSIX
+        // locales, which this shell does not have.
+        let style = style.strip_prefix("posix-").unwrap_or(style);
+        match style {
+            "full-iso" => Ok(TimeStyle::FullIso),
+            "long-iso" => Ok(TimeStyle::LongIso),
+            "iso" => Ok(TimeStyle::Iso),
+            "locale" => Ok(TimeStyle::Locale),
+            _ => {
+                let Some(format) = style.strip_prefix('+') else {
+                    return Err(
+                        ShellError::ExecutionError(format!("ls: invalid argument '{}' for '--time-style'", style))
+                    );
+                };
+                // `+RECENT\nOLD` gives a second format for older files.
+                let (recent, old) = format.split_once('\n').unwrap_or((format, format));
+                for part in [recent, old] {
+                    if StrftimeItems::new(part).any(|item| matches!(item, Item::Error)) {
+                        return Err(
+                            ShellError::ExecutionError(format!("ls: invalid time style format '{}'", part))
+                        );
+                    }
+                }
+                Ok(TimeStyle::Custom { recent: recent.to_string(), old: old.to_string() })
+            }
         }
     }
 
     fn list_file(&self, path: &Path, flags: &LsFlags) -> Result<(), ShellError> {
-        let name = path.to_string_lossy().to_string();
-
         if flags.long_format {
-            self.print_one_long(path.parent().unwrap_or(Path::new(".")), &name, flags)?;
+            // The name already holds the whole path as given.
+            self.print_one_long(Path::new(""), path.as_os_str(), flags)?;
         } else {
-            let mut display_name = name.clone();
+            let mut display_name = quoting::quote(path.as_os_str(), flags.quoting);
 
-            if let Ok(metadata) = fs::symlink_metadata(path) {
+            if let Ok(metadata) = self.entry_metadata(path, flags) {
                 if flags.file_indicators {
                     let ftype = metadata.file_type();
                     if ftype.is_dir() {
@@ -472,7 +1057,7 @@ impl LsCommand {
                         display_name.push('|');
                     } else if ftype.is_socket() {
                         display_name.push('=');
-                    } else if self.is_executable(&metadata) {
+                    } else if predicates::is_executable(&metadata) {
                         display_name.push('*');
                     }
                 }
@@ -486,36 +1071,66 @@ impl LsCommand {
         Ok(())
     }
 
-    fn list_files_together(&self, files: &[String], flags: &LsFlags) -> Result<(), ShellError> {
-        for file in files {
-            let path = Path::new(file);
-            let name = path.to_string_lossy().to_string();
-            let mut display_name = name.clone();
-            
-            if let Ok(metadata) = fs::symlink_metadata(path) {
-                if flags.file_indicators {
-                    let ftype = metadata.file_type();
-                    if ftype.is_dir() {
-                        display_name.push('/');
-                    } else if ftype.is_symlink() {
-                        display_name.push('@');
-                    } else if ftype.is_fifo() {
-                        display_name.push('|');
-                    } else if ftype.is_socket() {
-                        display_name.push('=');
-                    } else if self.is_executable(&metadata) {
-                        display_name.push('*');
+    fn list_files_together(&self, files: &[OsString], flags: &LsFlags) -> Result<(), ShellError> {
+        let entries: Vec<(OsString, PathBuf)> = files
+            .iter()
+            .map(|file| (file.clone(), PathBuf::from(file)))
+            .collect();
+        self.print_short(&entries, flags)
+    }
+
+    fn list_directory_contents(&self, path: &Path, flags: &LsFlags) -> Result<(), ShellError> {
+        let files = self.read_directory_entries(path, flags)?;
+        self.print_entries(path, &files, flags)
+    }
+
+    /// Lists `path` under a `path:` header, then each subdirectory in turn.
+    /// `ancestors` holds the (dev, inode) of the directories being listed
+    /// above this one, so a directory reachable from inside itself is
+    /// reported instead of recursed into forever. `separate` puts a blank
+    /// line before the header. A trapped signal stops the listing.
+    fn list_recursive(
+        &self,
+        path: &Path,
+        flags: &LsFlags,
+        ancestors: &mut HashSet<(u64, u64)>,
+        separate: bool
+    ) -> Result<(), ShellError> {
+        trap::check_interrupted()?;
+        let key = fs::metadata(path).map(|meta| (meta.dev(), meta.ino())).ok();
+        if let Some(key) = key
+            && !ancestors.insert(key)
+        {
+            eprintln!("ls: {}: not listing already-listed directory", path.display());
+            return Ok(());
+        }
+        if separate {
+            println!();
+        }
+        println!("{}:", quoting::quote(path.as_os_str(), flags.quoting));
+
+        match self.read_directory_entries(path, flags) {
+            Ok(files) => {
+                if let Err(e) = self.print_entries(path, &files, flags) {
+                    eprintln!("ls: {}: {}", path.display(), e);
+                }
+                for entry in &files {
+                    // Symlinks are listed but only followed under -L.
+                    if self.entry_metadata(&entry.path(), flags).is_ok_and(|m| m.is_dir()) {
+                        self.list_recursive(&entry.path(), flags, ancestors, true)?;
                     }
                 }
             }
-            
-            print!("{}  ", display_name);
+            Err(e) => eprintln!("ls: {}: {}", path.display(), e),
+        }
+
+        if let Some(key) = key {
+            ancestors.remove(&key);
         }
-        println!();
         Ok(())
     }
 
-    fn list_directory_contents(&self, path: &Path, flags: &LsFlags) -> Result<(), ShellError> {
+    fn read_directory_entries(&self, path: &Path, flags: &LsFlags) -> Result<Vec<fs::DirEntry>, ShellError> {
         let entries = fs::read_dir(path).map_err(|e| {
             match e.kind() {
                 io::ErrorKind::PermissionDenied => {
@@ -532,9 +1147,7 @@ impl LsCommand {
         for entry in entries {
             match entry {
                 Ok(entry) => {
-                    let name = entry.file_name().to_string_lossy().to_string();
-
-                    if !flags.show_hidden && name.starts_with('.') {
+                    if !flags.show_hidden && entry.file_name().as_bytes().starts_with(b".") {
                         continue;
                     }
 
@@ -546,35 +1159,72 @@ impl LsCommand {
             }
         }
 
-        files.sort_by(|a, b| {
-            let name_a = a
-                .file_name()
-                .to_string_lossy()
-                .to_string()
-                .to_lowercase()
-                .chars()
-                .filter(|c| c.is_ascii_alphanumeric())
-                .collect::<String>();
-            let name_b = b
-                .file_name()
-                .to_string_lossy()
-                .to_string()
-                .to_lowercase()
-                .chars()
-                .filter(|c| c.is_ascii_alphanumeric())
-                .collect::<String>();
-            name_a.cmp(&name_b)
+        Ok(self.sort_entries(files, flags))
+    }
+
+    fn sort_entries(&self, files: Vec<fs::DirEntry>, flags: &LsFlags) -> Vec<fs::DirEntry> {
+        if flags.sort == SortKey::Unsorted && !flags.group_directories_first {
+            return files;
+        }
+
+        // Metadata is looked up once per entry rather than per comparison.
+        let needs_metadata = matches!(flags.sort, SortKey::Time | SortKey::Size);
+        let mut keyed: Vec<(fs::DirEntry, String, CollationKey, Option<fs::Metadata>, bool)> = files
+            .into_iter()
+            .map(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let collation_key = flags.collation.key(&entry.file_name());
+                let metadata = if needs_metadata { self.entry_metadata(&entry.path(), flags).ok() } else { None };
+                // Symlinks to directories are grouped with the directories.
+                let is_dir = flags.group_directories_first
+                    && fs::metadata(entry.path()).is_ok_and(|m| m.is_dir());
+                (entry, name, collation_key, metadata, is_dir)
+            })
+            .collect();
+
+        keyed.sort_by(|(_, name_a, key_a, meta_a, _), (_, name_b, key_b, meta_b, _)| {
+            let by_key = match flags.sort {
+                SortKey::Name | SortKey::Unsorted => Ordering::Equal,
+                SortKey::Time => {
+                    let time = |m: &Option<fs::Metadata>| m.as_ref().and_then(|m| self.file_time(m, flags));
+                    time(meta_b).cmp(&time(meta_a))
+                }
+                SortKey::Size => {
+                    let size = |m: &Option<fs::Metadata>| m.as_ref().map_or(0, |m| m.len());
+                    size(meta_b).cmp(&size(meta_a))
+                }
+                SortKey::Extension => extension(name_a).cmp(extension(name_b)),
+                SortKey::Version => compare_versions(name_a, name_b),
+            };
+            let ordering = if flags.sort == SortKey::Unsorted {
+                Ordering::Equal
+            } else {
+                by_key.then_with(|| key_a.cmp(key_b))
+            };
+            if flags.reverse { ordering.reverse() } else { ordering }
         });
 
+        if flags.group_directories_first {
+            // A stable sort keeps the order chosen above within each group.
+            keyed.sort_by_key(|(_, _, _, _, is_dir)| !is_dir);
+        }
+
+        keyed.into_iter().map(|(entry, _, _, _, _)| entry).collect()
+    }
+
+    fn print_entries(&self, path: &Path, files: &[fs::DirEntry], flags: &LsFlags) -> Result<(), ShellError> {
         if flags.long_format {
-            self.print_total(path, &files, flags)?;
+            self.print_total(path, files, flags)?;
             if flags.show_hidden {
-                self.print_one_long(path, ".", flags)?;
-                self.print_one_long(path, "..", flags)?;
+                self.print_one_long(path, OsStr::new("."), flags)?;
+                self.print_one_long(path, OsStr::new(".."), flags)?;
             }
-            self.print_long_format(&files, flags)?;
+            self.print_long_format(files, flags)?;
         } else {
-            self.print_simple_format(&files, flags)?;
+            if flags.show_blocks {
+                self.print_total(path, files, flags)?;
+            }
+            self.print_simple_format(files, flags)?;
         }
 
         Ok(())
@@ -585,36 +1235,61 @@ impl LsCommand {
         files: &[fs::DirEntry],
         flags: &LsFlags
     ) -> Result<(), ShellError> {
-        for entry in files {
-            let name = entry.file_name().to_string_lossy().to_string();
-            let mut display_name = name.clone();
+        let entries: Vec<(OsString, PathBuf)> = files
+            .iter()
+            .map(|entry| (entry.file_name(), entry.path()))
+            .collect();
+        self.print_short(&entries, flags)
+    }
 
-            if let Ok(metadata) = fs::symlink_metadata(entry.path()) {
-                if flags.file_indicators {
-                    let ftype = metadata.file_type();
-                    if ftype.is_dir() {
-                        display_name.push('/');
-                    } else if ftype.is_symlink() {
-                        if !flags.long_format {
-                            display_name.push('@');
-                        }
-                    } else if ftype.is_fifo() {
-                        display_name.push('|');
-                    } else if ftype.is_socket() {
-                        display_name.push('=');
-                    } else if self.is_executable(&metadata) {
-                        display_name.push('*');
-                    }
+    /// Prints names with their `-i`/`-s` columns and `-F` indicators, laid
+    /// out by `flags.layout`. Each entry is the name to show and the path
+    /// to look it up by.
+    fn print_short(&self, entries: &[(OsString, PathBuf)], flags: &LsFlags) -> Result<(), ShellError> {
+        let metadata: Vec<Option<fs::Metadata>> = entries
+            .iter()
+            .map(|(_, path)| self.entry_metadata(path, flags).ok())
+            .collect();
+        let columns: Vec<(String, String)> = metadata
+            .iter()
+            .map(|meta| match meta {
+                Some(meta) => self.index_columns(meta, flags),
+                None => (String::new(), String::new()),
+            })
+            .collect();
+        let inode_width = columns.iter().map(|(inode, _)| inode.len()).max().unwrap_or(0);
+        let blocks_width = columns.iter().map(|(_, blocks)| blocks.len()).max().unwrap_or(0);
+
+        let mut cells = Vec::with_capacity(entries.len());
+        for (((name, path), meta), (inode, blocks)) in entries.iter().zip(&metadata).zip(&columns) {
+            let prefix = self.format_index_columns(inode, blocks, flags, inode_width, blocks_width);
+            let mut indicator = String::new();
+            if let Some(metadata) = meta
+                && flags.file_indicators
+            {
+                let ftype = metadata.file_type();
+                if ftype.is_dir() {
+                    indicator.push('/');
+                } else if ftype.is_symlink() {
+                    indicator.push('@');
+                } else if ftype.is_fifo() {
+                    indicator.push('|');
+                } else if ftype.is_socket() {
+                    indicator.push('=');
+                } else if predicates::is_executable(metadata) {
+                    indicator.push('*');
                 }
-
-                print!("{}  ", display_name);
-            } else {
-                print!("{}  ", display_name);
             }
+            // Escape sequences take no room on screen, so the width comes
+            // from the uncoloured text.
+            let width = columns::display_width(&prefix)
+                + columns::display_width(&quoting::quote(name, flags.quoting))
+                + indicator.len();
+            let text = format!("{}{}{}", prefix, self.paint(name, path, meta.as_ref(), flags), indicator);
+            cells.push(Cell { text, width });
         }
-        if files.len() > 0 {
-            println!();
-        }
+
+        columns::print_cells(&cells, flags.layout)?;
         Ok(())
     }
 
@@ -629,33 +1304,34 @@ impl LsCommand {
         let mut max_group_width = 0;
         let mut max_size_width = 0;
         let mut max_perms_width = 0;
+        let mut max_inode_width = 0;
+        let mut max_blocks_width = 0;
 
         for entry in files {
-            let name = entry.file_name().to_string_lossy().to_string();
-            let mut display_name = name.clone();
+            let name = entry.file_name();
+            let mut display_name = quoting::quote(&name, flags.quoting);
 
-            if let Ok(metadata) = entry.metadata() {
-                let perms = self.format_permissions_with_extended(&metadata, &entry.path());
+            if let Ok(metadata) = self.entry_metadata(&entry.path(), flags) {
+                display_name = self.paint(&name, &entry.path(), Some(&metadata), flags);
+                let perms = self.format_permissions_with_extended(&metadata, &entry.path(), flags);
+                let (inode, blocks) = self.index_columns(&metadata, flags);
 
                 let nlink = metadata.nlink();
 
                 let uid = metadata.uid();
                 let gid = metadata.gid();
-                let owner = self.get_owner_name(uid);
-                let group = self.get_group_name(gid);
+                let owner = self.owner_field(uid, flags);
+                let group = self.group_field(gid, flags);
 
                 let ftype = metadata.file_type();
                 let size_field = if ftype.is_char_device() || ftype.is_block_device() {
                     let (maj, min) = self.major_minor(metadata.rdev());
                     format!("{:>3}, {:>5}", maj, min)
                 } else {
-                    format!("{:>8}", metadata.len())
+                    format!("{:>8}", self.format_size(metadata.len(), flags))
                 };
 
-                let time_to_show = metadata
-                    .modified()
-                    .unwrap_or_else(|_| std::time::SystemTime::now());
-                let time_str = self.format_time(time_to_show);
+                let time_str = self.format_time(self.file_time(&metadata, flags), flags);
 
                 if flags.file_indicators {
                     let ftype = metadata.file_type();
@@ -667,7 +1343,7 @@ impl LsCommand {
                         display_name.push('|');
                     } else if ftype.is_socket() {
                         display_name.push('=');
-                    } else if !ftype.is_symlink() && self.is_executable(&metadata) {
+                    } else if !ftype.is_symlink() && predicates::is_executable(&metadata) {
                         display_name.push('*');
                     }
                 }
@@ -675,19 +1351,25 @@ impl LsCommand {
                 let link_suffix = if ftype.is_symlink() {
                     match std::fs::read_link(entry.path()) {
                         Ok(target) => {
-                            let mut target_str = target.display().to_string();
-                            if flags.file_indicators {
-                                if let Ok(target_meta) = fs::metadata(entry.path()) {
-                                    let target_type = target_meta.file_type();
-                                    if target_type.is_socket() {
-                                        target_str.push('=');
-                                    } else if target_type.is_dir() {
-                                        target_str.push('/');
-                                    } else if target_type.is_fifo() {
-                                        target_str.push('|');
-                                    } else if self.is_executable(&target_meta) {
-                                        target_str.push('*');
-                                    }
+                            let target_meta = fs::metadata(entry.path());
+                            let mut target_str = self.paint(
+                                target.as_os_str(),
+                                &entry.path(),
+                                target_meta.as_ref().ok(),
+                                flags
+                            );
+                            if flags.file_indicators
+                                && let Ok(target_meta) = target_meta
+                            {
+                                let target_type = target_meta.file_type();
+                                if target_type.is_socket() {
+                                    target_str.push('=');
+                                } else if target_type.is_dir() {
+                                    target_str.push('/');
+                                } else if target_type.is_fifo() {
+                                    target_str.push('|');
+                                } else if predicates::is_executable(&target_meta) {
+                                    target_str.push('*');
                                 }
                             }
                             format!(" -> {}", target_str)
@@ -699,12 +1381,16 @@ impl LsCommand {
                 };
 
                 max_perms_width = max_perms_width.max(perms.len());
+                max_inode_width = max_inode_width.max(inode.len());
+                max_blocks_width = max_blocks_width.max(blocks.len());
                 max_nlink_width = max_nlink_width.max(nlink.to_string().len());
                 max_owner_width = max_owner_width.max(owner.len());
                 max_group_width = max_group_width.max(group.len());
                 max_size_width = max_size_width.max(size_field.len());
 
                 file_data.push((
+                    inode,
+                    blocks,
                     perms,
                     nlink,
                     owner,
@@ -713,9 +1399,12 @@ impl LsCommand {
                     time_str,
                     display_name,
                     link_suffix,
+                    entry.path(),
                 ));
             } else {
                 file_data.push((
+                    "".to_string(),
+                    "".to_string(),
                     "".to_string(),
                     0,
                     "".to_string(),
@@ -724,11 +1413,14 @@ impl LsCommand {
                     "".to_string(),
                     display_name,
                     "".to_string(),
+                    entry.path(),
                 ));
             }
         }
 
         for (
+            inode,
+            blocks,
             perms,
             nlink,
             owner,
@@ -737,9 +1429,11 @@ impl LsCommand {
             time_str,
             display_name,
             link_suffix,
+            path,
         ) in file_data {
             println!(
-                "{:<perms_width$} {:>nlink_width$} {:<owner_width$} {:<group_width$} {:>size_width$} {} {}{}",
+                "{}{:<perms_width$} {:>nlink_width$} {:<owner_width$} {:<group_width$} {:>size_width$} {} {}{}",
+                self.format_index_columns(&inode, &blocks, flags, max_inode_width, max_blocks_width),
                 perms,
                 nlink,
                 owner,
@@ -754,6 +1448,9 @@ impl LsCommand {
                 group_width = max_group_width,
                 size_width = max_size_width
             );
+            if flags.show_xattrs {
+                self.print_xattrs(&path, flags);
+            }
         }
         Ok(())
     }
@@ -782,7 +1479,7 @@ impl LsCommand {
         perms.push(if (mode & 0o400) != 0 { 'r' } else { '-' });
         perms.push(if (mode & 0o200) != 0 { 'w' } else { '-' });
 
-        if (mode & 0o4000) != 0 {
+        if predicates::is_setuid(metadata) {
             perms.push(if (mode & 0o100) != 0 { 's' } else { 'S' });
         } else {
             perms.push(if (mode & 0o100) != 0 { 'x' } else { '-' });
@@ -791,7 +1488,7 @@ impl LsCommand {
         perms.push(if (mode & 0o040) != 0 { 'r' } else { '-' });
         perms.push(if (mode & 0o020) != 0 { 'w' } else { '-' });
 
-        if (mode & 0o2000) != 0 {
+        if predicates::is_setgid(metadata) {
             perms.push(if (mode & 0o010) != 0 { 's' } else { 'S' });
         } else {
             perms.push(if (mode & 0o010) != 0 { 'x' } else { '-' });
@@ -800,7 +1497,7 @@ impl LsCommand {
         perms.push(if (mode & 0o004) != 0 { 'r' } else { '-' });
         perms.push(if (mode & 0o002) != 0 { 'w' } else { '-' });
 
-        if (mode & 0o1000) != 0 {
+        if predicates::is_sticky(metadata) {
             perms.push(if (mode & 0o001) != 0 { 't' } else { 'T' });
         } else {
             perms.push(if (mode & 0o001) != 0 { 'x' } else { '-' });
@@ -809,77 +1506,57 @@ impl LsCommand {
         perms
     }
 
-    fn format_permissions_with_extended(&self, metadata: &fs::Metadata, path: &Path) -> String {
+    fn format_permissions_with_extended(&self, metadata: &fs::Metadata, path: &Path, flags: &LsFlags) -> String {
         let mut perms = self.format_permissions(metadata);
-
-        if self.has_extended_attributes(path) {
-            perms.push('+');
-        }
-
+        perms.extend(xattr::mode_marker(path, flags.dereference));
         perms
     }
 
-    fn has_extended_attributes(&self, path: &Path) -> bool {
-        let path_cstr = match std::ffi::CString::new(path.to_string_lossy().as_bytes()) {
-            Ok(s) => s,
-            Err(_) => {
-                return false;
-            }
-        };
-
-        unsafe {
-            let mut buffer = [0u8; 1024];
-            let result = libc::listxattr(
-                path_cstr.as_ptr(),
-                buffer.as_mut_ptr() as *mut i8,
-                buffer.len()
-            );
-
-            if result > 0 {
-                let attr_names = std::str::from_utf8(&buffer[..result as usize]).unwrap_or("");
-                for name in attr_names.split('\0') {
-                    if !name.is_empty() && self.is_extended_attribute(name) {
-                        return true;
-                    }
-                }
+    /// The timestamp picked by `-u`, `-c` or `--time`.
+    fn file_time(&self, metadata: &fs::Metadata, flags: &LsFlags) -> Option<SystemTime> {
+        match flags.time_field {
+            TimeField::Modified => metadata.modified().ok(),
+            TimeField::Accessed => metadata.accessed().ok(),
+            TimeField::Changed => {
+                let since_epoch = std::time::Duration::new(
+                    metadata.ctime().max(0) as u64,
+                    metadata.ctime_nsec().clamp(0, 999_999_999) as u32
+                );
+                Some(SystemTime::UNIX_EPOCH + since_epoch)
             }
+            TimeField::Birth => metadata.created().ok(),
         }
-
-        false
     }
 
-    fn is_extended_attribute(&self, name: &str) -> bool {
-        name.starts_with("system.posix_acl_") ||
-            name.starts_with("security.") ||
-            name.starts_with("trusted.") ||
-            name.starts_with("user.") ||
-            name.starts_with("system.") ||
-            name.starts_with("xattr.")
-    }
-
-    fn format_time(&self, time: SystemTime) -> String {
+    /// Formats a timestamp in the local time zone, taking `TZ` into account.
+    fn format_time(&self, time: Option<SystemTime>, flags: &LsFlags) -> String {
+        let Some(time) = time else {
+            return "-".to_string();
+        };
         let now = SystemTime::now();
         let duration = now.duration_since(time).unwrap_or_default();
 
         let six_months = std::time::Duration::from_secs(6 * 30 * 24 * 60 * 60);
-
-        let datetime: DateTime<Local> = time.into();
-
-        if duration > six_months {
-            datetime.format("%b %d  %Y").to_string()
-        } else {
-            datetime.format("%b %d %H:%M").to_string()
-        }
-    }
-
-    fn is_executable(&self, metadata: &fs::Metadata) -> bool {
-        let mode = metadata.permissions().mode();
-        (mode & 0o111) != 0
+        let recent = duration <= six_months;
+
+        let datetime = local_datetime(time);
+
+        let format = match &flags.time_style {
+            TimeStyle::FullIso => "%Y-%m-%d %H:%M:%S%.9f %z",
+            TimeStyle::LongIso => "%Y-%m-%d %H:%M",
+            TimeStyle::Iso if recent => "%m-%d %H:%M",
+            TimeStyle::Iso => "%Y-%m-%d ",
+            TimeStyle::Custom { recent: format, .. } if recent => format,
+            TimeStyle::Custom { old, .. } => old,
+            TimeStyle::Locale if recent => "%b %d %H:%M",
+            TimeStyle::Locale => "%b %d  %Y",
+        };
+        datetime.format(format).to_string()
     }
 }
 
 impl CommandExecutor for CatCommand {
-    fn execute(&self, args: &[String]) -> Result<(), ShellError> {
+    fn execute(&self, args: &[OsString]) -> Result<(), ShellError> {
         let mut command_options = CommandOptions {
             is_option: true,
         };
@@ -896,13 +1573,13 @@ impl CommandExecutor for CatCommand {
                 }
                 continue;
             }
-            if file_path.starts_with('-') && file_path != "-" && command_options.is_option {
-                return Err(ShellError::InvalidOption(file_path.clone()));
+            if file_path.as_bytes().starts_with(b"-") && file_path != "-" && command_options.is_option {
+                return Err(ShellError::InvalidOption(file_path.to_string_lossy().into_owned()));
             }
             match self.process_file(file_path) {
                 Ok(_) => {}
                 Err(e) => {
-                    eprintln!("cat: {}: {}", file_path, e);
+                    eprintln!("cat: {}: {}", Path::new(file_path).display(), e);
                 }
             }
         }
@@ -917,8 +1594,9 @@ impl CommandExecutor for CatCommand {
 
 impl CatCommand {
     fn read_from_stdin(&self) -> Result<(), ShellError> {
-        let stdin = io::stdin();
-        let mut handle = stdin.lock();
+        // Read descriptor 0 directly: `io::stdin()` may still hold buffered
+        // shell input, while fd 0 may have been redirected to a here-document.
+        let mut handle = ManuallyDrop::new(unsafe { fs::File::from_raw_fd(0) });
         let mut stdout = io::stdout();
 
         let mut buffer = [0; 8192];
@@ -928,7 +1606,7 @@ impl CatCommand {
                     break;
                 }
                 Ok(n) => {
-                    stdout.write_all(&buffer[..n]).map_err(|e| ShellError::IoError(e))?;
+                    stdout.write_all(&buffer[..n]).map_err(ShellError::IoError)?;
                 }
                 Err(e) => {
                     return Err(ShellError::IoError(e));
@@ -936,11 +1614,11 @@ impl CatCommand {
             }
         }
 
-        stdout.flush().map_err(|e| ShellError::IoError(e))?;
+        stdout.flush().map_err(ShellError::IoError)?;
         Ok(())
     }
 
-    fn process_file(&self, file_path: &str) -> Result<(), ShellError> {
+    fn process_file(&self, file_path: &OsStr) -> Result<(), ShellError> {
         if file_path == "-" {
             return self.read_from_stdin();
         }
@@ -962,18 +1640,18 @@ impl CatCommand {
         io
             ::stdout()
             .write_all(&content)
-            .map_err(|e| ShellError::IoError(e))?;
+            .map_err(ShellError::IoError)?;
         io
             ::stdout()
             .flush()
-            .map_err(|e| ShellError::IoError(e))?;
+            .map_err(ShellError::IoError)?;
 
         Ok(())
     }
 }
 
 impl CommandExecutor for MkdirCommand {
-    fn execute(&self, args: &[String]) -> Result<(), ShellError> {
+    fn execute(&self, args: &[OsString]) -> Result<(), ShellError> {
         if args.is_empty() {
             return Err(ShellError::ExecutionError("mkdir: missing operand".to_string()));
         }
@@ -989,9 +1667,11 @@ impl CommandExecutor for MkdirCommand {
             }
             if arg == "-p" && is_option {
                 create_parents = true;
-            } else if arg.starts_with('-') && is_option {
+            } else if arg.as_bytes().starts_with(b"-") && is_option {
                 return Err(
-                    ShellError::ExecutionError(format!("mkdir: invalid option -- '{}'", &arg[1..]))
+                    ShellError::ExecutionError(
+                        format!("mkdir: invalid option -- '{}'", &arg.to_string_lossy()[1..])
+                    )
                 );
             } else {
                 directories.push(arg);
@@ -1008,7 +1688,7 @@ impl CommandExecutor for MkdirCommand {
                     ::create_dir_all(dir_path)
                     .map_err(|e|
                         ShellError::FileSystemError(
-                            format!("Failed to create directory '{}': {}", dir_path, e)
+                            format!("Failed to create directory '{}': {}", Path::new(dir_path).display(), e)
                         )
                     )?;
             } else {
@@ -1016,7 +1696,7 @@ impl CommandExecutor for MkdirCommand {
                     ::create_dir(dir_path)
                     .map_err(|e|
                         ShellError::FileSystemError(
-                            format!("Failed to create directory '{}': {}", dir_path, e)
+                            format!("Failed to create directory '{}': {}", Path::new(dir_path).display(), e)
                         )
                     )?;
             }
@@ -1031,102 +1711,213 @@ impl CommandExecutor for MkdirCommand {
 }
 
 impl CommandExecutor for CpCommand {
-    fn execute(&self, args: &[String]) -> Result<(), ShellError> {
+    fn execute(&self, args: &[OsString]) -> Result<(), ShellError> {
         if args.len() < 2 {
             return Err(ShellError::ExecutionError("cp: missing operand".to_string()));
         }
 
-        let mut recursive = false;
+        let mut options = CopyOptions::new();
+        let mut dereference = None;
+        let mut show_progress = false;
         let mut is_option = true;
-        let mut filtered: Vec<&String> = Vec::new();
+        let mut filtered: Vec<&OsString> = Vec::new();
 
-        for arg in args {
-            if arg == "--" {
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" && is_option {
                 is_option = false;
                 continue;
             }
-            if arg == "-r" && is_option {
-                recursive = true;
-            } else if arg.starts_with('-') && is_option {
-                return Err(ShellError::InvalidOption(arg.clone()));
+            let option = match arg.to_str() {
+                Some(option) if is_option && option.starts_with('-') && option != "-" => option,
+                _ => {
+                    filtered.push(arg);
+                    continue;
+                }
+            };
+            if option == "--recursive" {
+                options.recursive = true;
+            } else if option == "--archive" {
+                options.recursive = true;
+                options.preserve = Preserve::all();
+                dereference = Some(Dereference::Never);
+            } else if option == "--no-dereference" {
+                dereference = Some(Dereference::Never);
+            } else if option == "--dereference" {
+                dereference = Some(Dereference::Always);
+            } else if option == "--verbose" {
+                options.verbose = true;
+            } else if option == "--progress" {
+                show_progress = true;
+            } else if let Some(count) = option.strip_prefix("--jobs=") {
+                options.jobs = parse_jobs(count)?;
+            } else if option == "--reflink" {
+                options.reflink = Reflink::Always;
+            } else if let Some(when) = option.strip_prefix("--reflink=") {
+                options.reflink = Reflink::parse(when).ok_or_else(|| {
+                    ShellError::ExecutionError(format!("cp: invalid argument '{}' for '--reflink'", when))
+                })?;
+            } else if let Some(when) = option.strip_prefix("--sparse=") {
+                options.sparse = Sparse::parse(when).ok_or_else(|| {
+                    ShellError::ExecutionError(format!("cp: invalid argument '{}' for '--sparse'", when))
+                })?;
+            } else if option == "--preserve" {
+                options.preserve.add_basic();
+            } else if let Some(list) = option.strip_prefix("--preserve=") {
+                options.preserve.add_list(list).map_err(|word| {
+                    ShellError::ExecutionError(format!("cp: invalid argument '{}' for '--preserve'", word))
+                })?;
+            } else if options.overwrite.parse_long(option)? {
+                continue;
+            } else if option.starts_with("--") {
+                return Err(ShellError::InvalidOption(option.to_string()));
             } else {
-                filtered.push(arg);
+                let flags = &option[1..];
+                for (i, c) in flags.char_indices() {
+                    match c {
+                        'r' | 'R' => options.recursive = true,
+                        'p' => options.preserve.add_basic(),
+                        // Archive mode: everything, recursively, with
+                        // symlinks as they are.
+                        'a' => {
+                            options.recursive = true;
+                            options.preserve = Preserve::all();
+                            dereference = Some(Dereference::Never);
+                        }
+                        'P' => dereference = Some(Dereference::Never),
+                        'L' => dereference = Some(Dereference::Always),
+                        'H' => dereference = Some(Dereference::CommandLine),
+                        'd' => {
+                            dereference = Some(Dereference::Never);
+                            options.preserve.links = true;
+                        }
+                        'v' => options.verbose = true,
+                        // `-jN` or `-j N`.
+                        'j' => {
+                            let rest = &flags[i + 1..];
+                            let count = if rest.is_empty() {
+                                args.next().map(|arg| arg.to_string_lossy().into_owned()).unwrap_or_default()
+                            } else {
+                                rest.to_string()
+                            };
+                            options.jobs = parse_jobs(&count)?;
+                            break;
+                        }
+                        c if options.overwrite.parse_flag(c) => {}
+                        _ => return Err(ShellError::InvalidOption(format!("-{}", c))),
+                    }
+                }
             }
         }
 
         if filtered.len() < 2 {
             return Err(ShellError::ExecutionError("cp: missing an operand".to_string()));
         }
+        // Like GNU cp, a recursive copy leaves symlinks alone unless told
+        // otherwise, and a plain one follows them.
+        options.dereference = dereference.unwrap_or(
+            if options.recursive { Dereference::Never } else { Dereference::Always }
+        );
+
+        // Only one question can be asked at a time.
+        if options.overwrite.clobber == Clobber::Ask {
+            options.jobs = 1;
+        }
 
         let target = Path::new(filtered.pop().unwrap());
         let sources = filtered;
 
+        if show_progress {
+            let (bytes, files) = sources
+                .iter()
+                .map(|src| copy::measure(Path::new(src), &options))
+                .fold((0, 0), |(bytes, files), (more_bytes, more_files)| (bytes + more_bytes, files + more_files));
+            options.progress = Some(Arc::new(Progress::new(bytes, files)));
+        }
+
         let mut has_errors = false;
         
         for src in sources {
-            let src_path = Path::new(src);
-
-            if !src_path.exists() {
-                eprintln!("cp: cannot stat '{}': No such file or directory", src);
+            // A trapped signal leaves the rest for its trap to deal with.
+            if trap::interrupted() {
                 has_errors = true;
-                continue;
+                break;
+            }
+            let src_path = Path::new(src);
+            let src = src_path.display();
+            // Errors below go on a line of their own.
+            if let Some(progress) = options.progress.as_deref() {
+                progress.clear();
             }
 
-            if !src_path.is_file() && !src_path.is_dir() {
-                eprintln!("cp: cannot copy '{}': Not a regular file or directory", src);
+            let metadata = match options.metadata(src_path, true) {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    eprintln!("cp: cannot stat '{}': {}", src, describe_io_error(&e));
+                    has_errors = true;
+                    continue;
+                }
+            };
+
+            let dest_path = if target.is_dir() {
+                target.join(src_path.file_name().unwrap_or_else(|| std::ffi::OsStr::new("")))
+            } else {
+                target.to_path_buf()
+            };
+
+            if let Ok(dest_metadata) = fs::metadata(&dest_path)
+                && let Ok(src_followed) = fs::metadata(src_path)
+                && dest_metadata.dev() == src_followed.dev()
+                && dest_metadata.ino() == src_followed.ino()
+                && !metadata.file_type().is_symlink()
+            {
+                eprintln!("cp: '{}' and '{}' are the same file", src, dest_path.display());
                 has_errors = true;
                 continue;
             }
 
-            if src_path.is_dir() {
-                if !recursive {
+            if metadata.is_dir() {
+                if !options.recursive {
                     eprintln!("cp: omitting directory '{}', use -r to copy", src);
                     has_errors = true;
                     continue;
                 }
-                
-                let dest_path = if target.is_dir() {
-                    target.join(src_path.file_name().unwrap_or_else(|| std::ffi::OsStr::new("")))
-                } else {
-                    target.to_path_buf()
-                };
-                
-                if dest_path.exists() && dest_path.is_file() {
+
+                if dest_path.exists() && !dest_path.is_dir() {
                     eprintln!("cp: cannot overwrite non-directory '{}' with directory '{}'", 
                              dest_path.display(), src);
                     has_errors = true;
                     continue;
                 }
-                
-                if let Err(err) = copy_dir_all(src_path, &dest_path) {
-                    match err.kind() {
-                        io::ErrorKind::PermissionDenied => {
-                            eprintln!("cp: cannot copy '{}': Permission denied", src);
-                        }
-                        io::ErrorKind::AlreadyExists => {
-                            eprintln!("cp: cannot copy '{}': Destination already exists", src);
-                        }
-                        _ => {
-                            eprintln!("cp: failed to copy directory '{}': {}", src, err);
-                        }
+
+                if is_inside(src_path, &dest_path) {
+                    eprintln!("cp: cannot copy a directory, '{}', into itself, '{}'", src, dest_path.display());
+                    has_errors = true;
+                    continue;
+                }
+
+                let errors = copy::copy_dir(src_path, &dest_path, &options);
+                if !errors.is_empty() {
+                    if let Some(progress) = options.progress.as_deref() {
+                        progress.clear();
+                    }
+                    for failure in errors {
+                        eprintln!("cp: cannot copy '{}': {}", failure.path.display(), describe_io_error(&failure.error));
                     }
                     has_errors = true;
                 }
             } else {
-                let dest_path = if target.is_dir() {
-                    target.join(src_path.file_name().unwrap_or_else(|| std::ffi::OsStr::new("")))
-                } else {
-                    target.to_path_buf()
-                };
-
-                if dest_path.exists() && dest_path.is_dir() {
+                if dest_path.is_dir() {
                     eprintln!("cp: cannot overwrite directory '{}' with non-directory '{}'", 
                              dest_path.display(), src);
                     has_errors = true;
                     continue;
                 }
 
-                if let Err(err) = fs::copy(src_path, &dest_path) {
+                if let Err(err) = copy::copy_file(src_path, &dest_path, &metadata, &options) {
+                    if let Some(progress) = options.progress.as_deref() {
+                        progress.clear();
+                    }
                     match err.kind() {
                         io::ErrorKind::PermissionDenied => {
                             eprintln!("cp: cannot copy '{}': Permission denied", src);
@@ -1146,6 +1937,9 @@ impl CommandExecutor for CpCommand {
             }
         }
         
+        if let Some(progress) = options.progress.as_deref() {
+            progress.finish();
+        }
         if has_errors {
             return Err(ShellError::ExecutionError("Some files could not be copied".to_string()));
         }
@@ -1154,43 +1948,31 @@ impl CommandExecutor for CpCommand {
     }
 
     fn help(&self) -> &str {
-        "Usage: cp source... destination - Copy files and directories"
+        "Usage: cp [-rpaPLHdinubv] [-j N] [--progress] [--reflink[=WHEN]] [--sparse=WHEN] [--preserve[=LIST]] [--backup[=CONTROL]] [--suffix=SUFFIX] source... destination - Copy files and directories (LIST: mode,ownership,timestamps,xattr,links,all; CONTROL: none,simple,numbered,existing; WHEN: auto,always,never)"
     }
 }
 
-fn copy_dir_all(src: &Path, dst: &Path) -> io::Result<()> {
-    fs::create_dir_all(dst)?;
-
-    for entry in fs::read_dir(src)? {
-        let entry = entry?;
-        let path = entry.path();
-        let dst_path = dst.join(entry.file_name());
-
-        if path.is_dir() {
-            copy_dir_all(&path, &dst_path)?;
-        } else if path.is_file() {
-            fs::copy(&path, &dst_path)?;
-        }
-    }
-    Ok(())
-}
-
 impl CommandExecutor for MvCommand {
-    fn execute(&self, args: &[String]) -> Result<(), ShellError> {
+    fn execute(&self, args: &[OsString]) -> Result<(), ShellError> {
         if args.len() < 2 {
             return Err(ShellError::ExecutionError("mv: missing operand".to_string()));
         }
 
         let mut is_option = true;
-        let mut filtered: Vec<&String> = Vec::new();
+        let mut verbose = false;
+        let mut filtered: Vec<&OsString> = Vec::new();
 
         for arg in args {
             if arg == "--" {
                 is_option = false;
                 continue;
             }
-            if arg.starts_with('-') && is_option {
-                return Err(ShellError::InvalidOption(arg.clone()));
+            if arg.as_bytes().starts_with(b"-") && is_option {
+                if arg == "--verbose" || arg == "-v" {
+                    verbose = true;
+                } else {
+                    return Err(ShellError::InvalidOption(arg.to_string_lossy().into_owned()));
+                }
             } else {
                 filtered.push(arg);
             }
@@ -1202,24 +1984,24 @@ impl CommandExecutor for MvCommand {
 
         let target = Path::new(&filtered[filtered.len() - 1]);
         let sources = &filtered[..filtered.len() - 1];
+        // Shared by all the sources, so hard links between them survive a
+        // move to another filesystem.
+        let copy_options = CopyOptions::for_move();
 
         let mut has_errors = false;
         
         for src in sources {
             let src_path = Path::new(src);
+            let src = src_path.display();
 
-            if !src_path.exists() {
+            // The source itself is moved, so a dangling symlink, a fifo or
+            // a socket is as good as a regular file.
+            if fs::symlink_metadata(src_path).is_err() {
                 eprintln!("mv: cannot stat '{}': No such file or directory", src);
                 has_errors = true;
                 continue;
             }
 
-            if !src_path.is_file() && !src_path.is_dir() {
-                eprintln!("mv: cannot move '{}': Not a regular file or directory", src);
-                has_errors = true;
-                continue;
-            }
-
             let dest_path = if target.is_dir() {
                 target.join(src_path.file_name().unwrap_or_else(|| std::ffi::OsStr::new("")))
             } else {
@@ -1240,25 +2022,39 @@ impl CommandExecutor for MvCommand {
                 }
             }
 
-            if let Err(err) = fs::rename(src_path, &dest_path) {
-                match err.kind() {
-                    io::ErrorKind::PermissionDenied => {
-                        eprintln!("mv: cannot move '{}': Permission denied", src);
+            let moved = match fs::rename(src_path, &dest_path) {
+                Ok(()) => true,
+                // rename cannot leave the filesystem, so the file is copied
+                // with everything that can be kept and then removed.
+                Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+                    let failures = copy::move_across(src_path, &dest_path, &copy_options);
+                    for failure in &failures {
+                        eprintln!("mv: cannot move '{}': {}", failure.path.display(), describe_io_error(&failure.error));
                     }
-                    io::ErrorKind::NotFound => {
-                        eprintln!("mv: cannot move '{}': Destination directory does not exist", src);
-                    }
-                    io::ErrorKind::InvalidInput => {
-                        eprintln!("mv: cannot move '{}': Invalid input", src);
-                    }
-                    io::ErrorKind::CrossesDevices => {
-                        eprintln!("mv: cannot move '{}': Cross-device link not permitted", src);
-                    }
-                    _ => {
-                        eprintln!("mv: failed to move '{}': {}", src, err);
+                    failures.is_empty()
+                }
+                Err(err) => {
+                    match err.kind() {
+                        io::ErrorKind::PermissionDenied => {
+                            eprintln!("mv: cannot move '{}': Permission denied", src);
+                        }
+                        io::ErrorKind::NotFound => {
+                            eprintln!("mv: cannot move '{}': Destination directory does not exist", src);
+                        }
+                        io::ErrorKind::InvalidInput => {
+                            eprintln!("mv: cannot move '{}': Invalid input", src);
+                        }
+                        _ => {
+                            eprintln!("mv: failed to move '{}': {}", src, err);
+                        }
                     }
+                    false
                 }
+            };
+            if !moved {
                 has_errors = true;
+            } else if verbose {
+                println!("renamed {} -> {}", copy::quote_path(src_path), copy::quote_path(&dest_path));
             }
         }
         
@@ -1270,12 +2066,12 @@ impl CommandExecutor for MvCommand {
     }
 
     fn help(&self) -> &str {
-        "Usage: mv source... destination - Move (rename) files or directories"
+        "Usage: mv [-v] source... destination - Move (rename) files or directories"
     }
 }
 impl CommandExecutor for RmCommand {
-    fn execute(&self, args: &[String]) -> Result<(), ShellError> {
-        let pwd = env::current_dir().unwrap().to_string_lossy().to_string();
+    fn execute(&self, args: &[OsString]) -> Result<(), ShellError> {
+        let pwd = env::current_dir().unwrap().into_os_string();
 
         if args.is_empty() {
             return Err(ShellError::ExecutionError("rm: missing operand".to_string()));
@@ -1283,10 +2079,10 @@ impl CommandExecutor for RmCommand {
 
         let mut recursive = false;
         let mut is_option = true;
-        let mut targets: Vec<&String> = Vec::new();
+        let mut targets: Vec<&OsString> = Vec::new();
 
         for arg in args {
-            if arg.as_str() == pwd {
+            if *arg == pwd {
                 return Err(
                     ShellError::ExecutionError(
                         "rm: refusing to remove current directory".to_string()
@@ -1304,8 +2100,8 @@ impl CommandExecutor for RmCommand {
             }
             if arg == "-r" && is_option {
                 recursive = true;
-            } else if arg.starts_with('-') && is_option {
-                return Err(ShellError::InvalidOption(arg.clone()));
+            } else if arg.as_bytes().starts_with(b"-") && is_option {
+                return Err(ShellError::InvalidOption(arg.to_string_lossy().into_owned()));
             } else {
                 targets.push(arg);
             }
@@ -1318,26 +2114,22 @@ impl CommandExecutor for RmCommand {
         
         for target in targets {
             let path = Path::new(target);
+            let target = path.display();
 
-            if !path.exists() {
+            // Not following symlinks, so that a dangling one can be removed
+            // and a link to a directory is removed rather than the directory.
+            let Ok(metadata) = fs::symlink_metadata(path) else {
                 eprintln!("rm: cannot remove '{}': No such file or directory", target);
                 has_errors = true;
                 continue;
-            }
-
-            if !path.is_file() && !path.is_dir() {
-                eprintln!("rm: cannot remove '{}': Not a regular file or directory", target);
-                has_errors = true;
-                continue;
-            }
+            };
 
-            let result = if path.is_dir() {
+            let result = if metadata.is_dir() {
                 if recursive {
                     fs::remove_dir_all(path)
                 } else {
                     Err(
-                        std::io::Error::new(
-                            std::io::ErrorKind::Other,
+                        std::io::Error::other(
                             format!("rm: cannot remove '{}': Is a directory (use -r)", target)
                         )
                     )
@@ -1375,6 +2167,396 @@ impl CommandExecutor for RmCommand {
         "Usage: rm [-r] [file...] - Remove files or directories"
     }
 }
-fn is_dot_or_dotdot(path: &str) -> bool {
+impl CommandExecutor for GetfaclCommand {
+    fn execute(&self, args: &[OsString]) -> Result<(), ShellError> {
+        let mut access = true;
+        let mut default = true;
+        let mut header = true;
+        let mut numeric = false;
+        let mut is_option = true;
+        let mut files: Vec<&OsString> = Vec::new();
+
+        for arg in args {
+            if arg == "--" && is_option {
+                is_option = false;
+                continue;
+            }
+            match arg.to_str() {
+                Some(option) if is_option && option.starts_with('-') && option.len() > 1 => {
+                    for c in option[1..].chars() {
+                        match c {
+                            'a' => default = false,
+                            'd' => access = false,
+                            'c' => header = false,
+                            'n' => numeric = true,
+                            _ => {
+                                return Err(
+                                    ShellError::ExecutionError(format!("getfacl: invalid option -- '{}'", c))
+                                );
+                            }
+                        }
+                    }
+                }
+                _ => files.push(arg),
+            }
+        }
+
+        if files.is_empty() {
+            return Err(ShellError::ExecutionError("getfacl: missing operand".to_string()));
+        }
+
+        let mut has_errors = false;
+        let mut warned_absolute = false;
+        for file in files {
+            let path = Path::new(file);
+            let metadata = match fs::metadata(path) {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    eprintln!("getfacl: {}: {}", path.display(), describe_io_error(&e));
+                    has_errors = true;
+                    continue;
+                }
+            };
+            let user = |uid: u32| if numeric { uid.to_string() } else { LsCommand.lookup_owner_name(uid) };
+            let group = |gid: u32| if numeric { gid.to_string() } else { LsCommand.lookup_group_name(gid) };
+
+            if header {
+                let shown = match file.as_bytes().strip_prefix(b"/") {
+                    Some(relative) if !relative.is_empty() => {
+                        if !warned_absolute {
+                            eprintln!("getfacl: Removing leading '/' from absolute path names");
+                            warned_absolute = true;
+                        }
+                        OsStr::from_bytes(relative)
+                    }
+                    _ => file.as_os_str(),
+                };
+                println!("# file: {}", quoting::quote(shown, QuotingStyle::Escape));
+                println!("# owner: {}", user(metadata.uid()));
+                println!("# group: {}", group(metadata.gid()));
+            }
+
+            let read_acl = |name: &str| -> Result<Option<Vec<AclEntry>>, ShellError> {
+                match xattr::get(path, OsStr::new(name), true) {
+                    Ok(Some(value)) => xattr::parse_acl(&value).map(Some).ok_or_else(|| {
+                        ShellError::FileSystemError(format!("getfacl: {}: Invalid ACL", path.display()))
+                    }),
+                    Ok(None) => Ok(None),
+                    Err(e) => Err(
+                        ShellError::FileSystemError(format!("getfacl: {}: {}", path.display(), describe_io_error(&e)))
+                    ),
+                }
+            };
+
+            if access {
+                // Without the attribute, the mode bits are the whole ACL.
+                match read_acl(xattr::ACL_ACCESS) {
+                    Ok(acl) => {
+                        let acl = acl.unwrap_or_else(|| xattr::acl_from_mode(metadata.permissions().mode()));
+                        print_acl(&acl, "", &user, &group);
+                    }
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        has_errors = true;
+                    }
+                }
+            }
+            if default && metadata.is_dir() {
+                match read_acl(xattr::ACL_DEFAULT) {
+                    Ok(Some(acl)) => print_acl(&acl, "default:", &user, &group),
+                    Ok(None) => {}
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        has_errors = true;
+                    }
+                }
+            }
+            println!();
+        }
+
+        if has_errors {
+            return Err(ShellError::ExitStatus(1));
+        }
+        Ok(())
+    }
+
+    fn help(&self) -> &str {
+        "Usage: getfacl [-adcn] file... - Show the POSIX ACL of files (-a access only, -d default only, -c no header, -n numeric ids)"
+    }
+}
+
+/// Prints an ACL one entry per line, adding the rights left after the
+/// mask where it takes some away.
+fn print_acl(acl: &[AclEntry], prefix: &str, user: &dyn Fn(u32) -> String, group: &dyn Fn(u32) -> String) {
+    let mask = acl.iter().find(|entry| entry.tag == AclTag::Mask).map(|entry| entry.perm);
+    for entry in acl {
+        let text = acl_entry_text(entry, user, group);
+        let masked = matches!(entry.tag, AclTag::User | AclTag::GroupObj | AclTag::Group);
+        match mask {
+            Some(mask) if masked && entry.perm & !mask != 0 => {
+                println!("{}{}\t#effective:{}", prefix, text, xattr::perm_string(entry.perm & mask));
+            }
+            _ => println!("{}{}", prefix, text),
+        }
+    }
+}
+
+/// One ACL entry as getfacl writes it, e.g. `user:alice:rw-`.
+fn acl_entry_text(entry: &AclEntry, user: impl Fn(u32) -> String, group: impl Fn(u32) -> String) -> String {
+    let (tag, qualifier) = match entry.tag {
+        AclTag::UserObj => ("user", String::new()),
+        AclTag::User => ("user", entry.id.map(user).unwrap_or_default()),
+        AclTag::GroupObj => ("group", String::new()),
+        AclTag::Group => ("group", entry.id.map(group).unwrap_or_default()),
+        AclTag::Mask => ("mask", String::new()),
+        AclTag::Other => ("other", String::new()),
+    };
+    format!("{}:{}:{}", tag, qualifier, xattr::perm_string(entry.perm))
+}
+
+/// GNU-style human-readable size: one decimal below 10, rounded up, so a
+/// size is never shown smaller than it is.
+pub(super) fn human_size(bytes: u64, base: u64) -> String {
+    let units = if base == 1000 {
+        ["k", "M", "G", "T", "P", "E"]
+    } else {
+        ["K", "M", "G", "T", "P", "E"]
+    };
+    if bytes < base {
+        return bytes.to_string();
+    }
+    let base_f = base as f64;
+    let mut value = bytes as f64 / base_f;
+    let mut unit = 0;
+    while value >= base_f && unit + 1 < units.len() {
+        value /= base_f;
+        unit += 1;
+    }
+    if value < 10.0 {
+        let rounded = (value * 10.0).ceil() / 10.0;
+        if rounded < 10.0 {
+            return format!("{:.1}{}", rounded, units[unit]);
+        }
+    }
+    let rounded = value.ceil();
+    if rounded >= base_f && unit + 1 < units.len() {
+        return format!("1.0{}", units[unit + 1]);
+    }
+    format!("{}{}", rounded, units[unit])
+}
+
+/// The part after the last `.`, empty for names without one. A leading dot
+/// marks a hidden file rather than an extension.
+fn extension(name: &str) -> &str {
+    match name.rfind('.') {
+        Some(0) | None => "",
+        Some(i) => &name[i + 1..],
+    }
+}
+
+/// Compares names run by run, taking runs of digits by numeric value.
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        match (a.chars().next(), b.chars().next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let end_a = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+                let end_b = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+                let digits_a = a[..end_a].trim_start_matches('0');
+                let digits_b = b[..end_b].trim_start_matches('0');
+                let ordering = digits_a.len()
+                    .cmp(&digits_b.len())
+                    .then_with(|| digits_a.cmp(digits_b))
+                    .then_with(|| end_a.cmp(&end_b));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a = &a[end_a..];
+                b = &b[end_b..];
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a = &a[x.len_utf8()..];
+                b = &b[y.len_utf8()..];
+            }
+        }
+    }
+}
+
+// The libc crate only binds `tzset` for Windows.
+unsafe extern "C" {
+    fn tzset();
+}
+
+/// Converts to local time through libc. chrono caches the zone for a
+/// second at a time, which misses `TZ` set just for one command, so the
+/// zone is the one `tzset` last read when `ls` parsed its options.
+fn local_datetime(time: SystemTime) -> DateTime<FixedOffset> {
+    let utc: DateTime<Utc> = time.into();
+    let seconds = unsafe {
+        let timestamp = utc.timestamp() as libc::time_t;
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&timestamp, &mut tm).is_null() { 0 } else { tm.tm_gmtoff }
+    };
+    let offset = FixedOffset::east_opt(seconds as i32)
+        .unwrap_or_else(|| FixedOffset::east_opt(0).expect("zero offset is valid"));
+    utc.with_timezone(&offset)
+}
+
+/// A file name as a JSON string. Bytes that are not UTF-8 come out as the
+/// characters `encoding` maps them to, so every name stays distinct and
+/// can be turned back into the same bytes.
+fn json_name(name: &OsStr) -> String {
+    json_string(&encoding::decode_os(name))
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// `QUOTING_STYLE` if set, else names that could be misread are quoted on a
+/// terminal and left alone in pipes, as GNU ls does.
+fn default_quoting_style() -> QuotingStyle {
+    if let Some(style) = env::var("QUOTING_STYLE").ok().and_then(|word| QuotingStyle::parse(&word)) {
+        return style;
+    }
+    if unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1 {
+        QuotingStyle::ShellEscape
+    } else {
+        QuotingStyle::Literal
+    }
+}
+
+fn describe_io_error(error: &io::Error) -> String {
+    match error.kind() {
+        io::ErrorKind::NotFound => "No such file or directory".to_string(),
+        io::ErrorKind::PermissionDenied => "Permission denied".to_string(),
+        _ => error.to_string(),
+    }
+}
+
+/// The count of `cp -j`.
+fn parse_jobs(count: &str) -> Result<usize, ShellError> {
+    match count.parse::<usize>() {
+        Ok(jobs) if jobs > 0 => Ok(jobs),
+        _ => Err(ShellError::ExecutionError(format!("cp: invalid number of jobs: '{}'", count))),
+    }
+}
+
+/// True when `dest` is `dir` or somewhere below it, even if `dest` does
+/// not exist yet.
+fn is_inside(dir: &Path, dest: &Path) -> bool {
+    let Ok(dir) = fs::canonicalize(dir) else {
+        return false;
+    };
+    // The last part may be about to be created, so only its parent has
+    // to exist.
+    let dest = match fs::canonicalize(dest) {
+        Ok(dest) => dest,
+        Err(_) => match (dest.parent(), dest.file_name()) {
+            (Some(parent), Some(name)) => {
+                let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+                match fs::canonicalize(parent) {
+                    Ok(parent) => parent.join(name),
+                    Err(_) => return false,
+                }
+            }
+            _ => return false,
+        },
+    };
+    dest.starts_with(&dir)
+}
+
+fn is_dot_or_dotdot(path: &OsStr) -> bool {
     path == "." || path == ".." || path == "./" || path == "../"
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::ffi::OsStringExt;
+
+    #[test]
+    fn json_names_keep_invalid_bytes() {
+        let dir = env::temp_dir().join(format!("0-shell-json-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let (flags, _) = LsCommand.parse_args(&[OsString::from("--json")]).unwrap();
+
+        let mut names = Vec::new();
+        for bytes in [b"caf\xe9".to_vec(), b"caf\xe8".to_vec()] {
+            let name = OsString::from_vec(bytes.clone());
+            fs::write(dir.join(&name), "").unwrap();
+            let object = LsCommand.json_object(&name, &dir.join(&name), &flags).unwrap();
+            let field = object.split("\"name\":\"").nth(1).and_then(|rest| rest.split('"').next()).unwrap();
+            assert_eq!(encoding::encode_bytes(field), bytes);
+            names.push(field.to_string());
+        }
+        assert_ne!(names[0], names[1]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn version_order_compares_numbers_by_value() {
+        assert_eq!(compare_versions("file9", "file10"), Ordering::Less);
+        assert_eq!(compare_versions("file10", "file9"), Ordering::Greater);
+        assert_eq!(compare_versions("a1b2", "a1b10"), Ordering::Less);
+        assert_eq!(compare_versions("v1.2.10", "v1.10.2"), Ordering::Less);
+        assert_eq!(compare_versions("file", "file1"), Ordering::Less);
+        assert_eq!(compare_versions("same", "same"), Ordering::Equal);
+        // Equal values: the run with more leading zeros goes last.
+        assert_eq!(compare_versions("file007", "file7"), Ordering::Greater);
+        assert_eq!(compare_versions("file007", "file8"), Ordering::Less);
+    }
+
+    #[test]
+    fn extension_is_after_the_last_dot() {
+        assert_eq!(extension("archive.tar.gz"), "gz");
+        assert_eq!(extension("notes.txt"), "txt");
+        assert_eq!(extension("Makefile"), "");
+        assert_eq!(extension(".bashrc"), "");
+        assert_eq!(extension(".config.old"), "old");
+        assert_eq!(extension("trailing."), "");
+    }
+
+    #[test]
+    fn human_sizes_round_up_like_gnu() {
+        assert_eq!(human_size(0, 1024), "0");
+        assert_eq!(human_size(1023, 1024), "1023");
+        assert_eq!(human_size(1024, 1024), "1.0K");
+        assert_eq!(human_size(1025, 1024), "1.1K");
+        assert_eq!(human_size(10188, 1024), "10K");
+        assert_eq!(human_size(10241, 1024), "11K");
+        assert_eq!(human_size(1048575, 1024), "1.0M");
+        assert_eq!(human_size(5 << 30, 1024), "5.0G");
+    }
+
+    #[test]
+    fn si_sizes_use_powers_of_1000() {
+        assert_eq!(human_size(999, 1000), "999");
+        assert_eq!(human_size(1000, 1000), "1.0k");
+        assert_eq!(human_size(1001, 1000), "1.1k");
+        assert_eq!(human_size(1_500_000, 1000), "1.5M");
+        assert_eq!(human_size(999_999, 1000), "1.0M");
+        assert_eq!(human_size(123_456_789, 1000), "124M");
+    }
+}
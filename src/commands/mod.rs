@@ -1,15 +1,34 @@
 mod builtin;
+mod collation;
+mod colors;
+mod columns;
+mod conditional;
+mod copy;
 mod filesystem;
+mod overwrite;
+mod predicates;
+mod progress;
+mod quoting;
+mod xattr;
 
+pub use conditional::{ evaluate_conditional, CondArg };
+
+use crate::encoding;
 use crate::error::ShellError;
 use crate::parser::Command;
 use std::collections::HashMap;
+use std::ffi::OsString;
 
 pub trait CommandExecutor {
-    fn execute(&self, args: &[String]) -> Result<(), ShellError>;
+    fn execute(&self, args: &[OsString]) -> Result<(), ShellError>;
     fn help(&self) -> &str;
 }
 
+/// Arguments as text, for commands that never treat them as file names.
+pub fn text_args(args: &[OsString]) -> Vec<String> {
+    args.iter().map(|arg| encoding::decode_os(arg)).collect()
+}
+
 pub struct CommandRegistry {
     commands: HashMap<String, Box<dyn CommandExecutor>>,
 }
@@ -28,6 +47,10 @@ impl CommandRegistry {
     fn register_builtin_commands(&mut self) {
         self.commands.insert("echo".to_string(), Box::new(builtin::EchoCommand));
         self.commands.insert("exit".to_string(), Box::new(builtin::ExitCommand));
+        self.commands.insert("export".to_string(), Box::new(builtin::ExportCommand));
+        self.commands.insert("let".to_string(), Box::new(builtin::LetCommand));
+        self.commands.insert("test".to_string(), Box::new(conditional::TestCommand));
+        self.commands.insert("[".to_string(), Box::new(conditional::BracketCommand));
         
         self.commands.insert("pwd".to_string(), Box::new(filesystem::PwdCommand));
         self.commands.insert("cd".to_string(), Box::new(filesystem::CdCommand));
@@ -37,6 +60,8 @@ impl CommandRegistry {
         self.commands.insert("cp".to_string(), Box::new(filesystem::CpCommand));
         self.commands.insert("mv".to_string(), Box::new(filesystem::MvCommand));
         self.commands.insert("rm".to_string(), Box::new(filesystem::RmCommand));
+        self.commands.insert("getfacl".to_string(), Box::new(filesystem::GetfaclCommand));
+        self.commands.insert("dircolors".to_string(), Box::new(colors::DircolorsCommand));
         self.commands.insert("help".to_string(), Box::new(builtin::HelpCommand));
     }
 
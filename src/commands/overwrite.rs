@@ -0,0 +1,282 @@
+use crate::error::ShellError;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{ self, Read, Write };
+use std::mem::ManuallyDrop;
+use std::os::unix::ffi::{ OsStrExt, OsStringExt };
+use std::os::unix::io::FromRawFd;
+use std::path::{ Path, PathBuf };
+
+/// What to do when the destination already exists, shared by `cp` and
+/// `mv`.
+#[derive(Debug, Clone)]
+pub struct OverwritePolicy {
+    /// Used in the `-i` prompt.
+    pub command: &'static str,
+    pub clobber: Clobber,
+    /// `-u`: only replace a destination older than the source.
+    pub update: bool,
+    pub backup: Backup,
+    pub suffix: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Clobber {
+    Always,
+    /// `-i`: ask first.
+    Ask,
+    /// `-n`: never replace anything.
+    Never,
+}
+
+/// `--backup=CONTROL`: what an existing destination is renamed to before
+/// it is replaced.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backup {
+    None,
+    /// `name~`, or whatever `--suffix` says.
+    Simple,
+    /// `name.~1~`, `name.~2~`, ...
+    Numbered,
+    /// Numbered if there are numbered backups already, else simple.
+    Existing,
+}
+
+impl Backup {
+    fn parse(control: &str) -> Option<Self> {
+        match control {
+            "none" | "off" => Some(Backup::None),
+            "simple" | "never" => Some(Backup::Simple),
+            "numbered" | "t" => Some(Backup::Numbered),
+            "existing" | "nil" => Some(Backup::Existing),
+            _ => None,
+        }
+    }
+
+    /// What `-b` and a bare `--backup` mean: `VERSION_CONTROL`, else
+    /// `existing`.
+    fn from_env() -> Self {
+        std::env::var("VERSION_CONTROL").ok().and_then(|control| Backup::parse(&control)).unwrap_or(Backup::Existing)
+    }
+}
+
+impl OverwritePolicy {
+    pub fn new(command: &'static str) -> Self {
+        OverwritePolicy {
+            command,
+            clobber: Clobber::Always,
+            update: false,
+            backup: Backup::None,
+            suffix: std::env::var("SIMPLE_BACKUP_SUFFIX").unwrap_or_else(|_| "~".to_string()),
+        }
+    }
+
+    /// Takes a short option letter if it is one of `-i`, `-n`, `-u` or
+    /// `-b`. Of `-i` and `-n`, the last one given wins.
+    pub fn parse_flag(&mut self, flag: char) -> bool {
+        match flag {
+            'i' => self.clobber = Clobber::Ask,
+            'n' => self.clobber = Clobber::Never,
+            'u' => self.update = true,
+            'b' => self.backup = Backup::from_env(),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Takes a long option if it is one of this policy's.
+    pub fn parse_long(&mut self, option: &str) -> Result<bool, ShellError> {
+        match option {
+            "--interactive" => self.clobber = Clobber::Ask,
+            "--no-clobber" => self.clobber = Clobber::Never,
+            "--update" => self.update = true,
+            "--backup" => self.backup = Backup::from_env(),
+            _ => {
+                if let Some(control) = option.strip_prefix("--backup=") {
+                    self.backup = Backup::parse(control).ok_or_else(|| {
+                        ShellError::ExecutionError(
+                            format!("{}: invalid argument '{}' for '--backup'", self.command, control)
+                        )
+                    })?;
+                } else if let Some(suffix) = option.strip_prefix("--suffix=") {
+                    if suffix.is_empty() || suffix.contains('/') {
+                        return Err(
+                            ShellError::ExecutionError(format!("{}: invalid suffix '{}'", self.command, suffix))
+                        );
+                    }
+                    self.suffix = suffix.to_string();
+                } else {
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Decides whether `src` may replace `dst`, asking under `-i`, and
+    /// moves `dst` out of the way when backups are on. `Ok(false)` means
+    /// the file is to be left as it is.
+    pub fn allows(&self, src: &fs::Metadata, dst: &Path) -> io::Result<bool> {
+        let Ok(existing) = fs::symlink_metadata(dst) else {
+            return Ok(true);
+        };
+        // Directories are merged into, never replaced.
+        if existing.is_dir() {
+            return Ok(true);
+        }
+        if self.update
+            && let (Ok(src_time), Ok(dst_time)) = (src.modified(), existing.modified())
+            && dst_time >= src_time
+        {
+            return Ok(false);
+        }
+        match self.clobber {
+            Clobber::Never => return Ok(false),
+            Clobber::Ask if !confirm(&format!("{}: overwrite '{}'? ", self.command, dst.display()))? => {
+                return Ok(false);
+            }
+            _ => {}
+        }
+        if let Some(backup) = self.backup_name(dst)? {
+            fs::rename(dst, backup)?;
+        }
+        Ok(true)
+    }
+
+    /// The name `dst` is backed up to, if backups are on.
+    fn backup_name(&self, dst: &Path) -> io::Result<Option<PathBuf>> {
+        let simple = || {
+            let mut name = dst.as_os_str().to_os_string();
+            name.push(&self.suffix);
+            PathBuf::from(name)
+        };
+        let highest = || -> io::Result<u64> { highest_backup_number(dst) };
+        Ok(match self.backup {
+            Backup::None => None,
+            Backup::Simple => Some(simple()),
+            Backup::Numbered => Some(numbered_name(dst, highest()? + 1)),
+            Backup::Existing => match highest()? {
+                0 => Some(simple()),
+                n => Some(numbered_name(dst, n + 1)),
+            },
+        })
+    }
+}
+
+fn numbered_name(dst: &Path, number: u64) -> PathBuf {
+    let mut name = dst.as_os_str().to_os_string();
+    name.push(format!(".~{}~", number));
+    PathBuf::from(name)
+}
+
+/// The largest N for which `dst.~N~` exists, or 0.
+fn highest_backup_number(dst: &Path) -> io::Result<u64> {
+    let Some(file_name) = dst.file_name() else {
+        return Ok(0);
+    };
+    let dir = match dst.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut prefix = file_name.as_bytes().to_vec();
+    prefix.extend_from_slice(b".~");
+
+    let mut highest = 0;
+    for entry in fs::read_dir(dir)? {
+        let name = OsString::into_vec(entry?.file_name());
+        let number = name
+            .strip_prefix(prefix.as_slice())
+            .and_then(|rest| rest.strip_suffix(b"~"))
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| digits.parse::<u64>().ok());
+        if let Some(number) = number {
+            highest = highest.max(number);
+        }
+    }
+    Ok(highest)
+}
+
+/// Prints `question` on stderr and reads a line from descriptor 0, which
+/// may have been redirected. Only an answer starting with `y` says yes.
+fn confirm(question: &str) -> io::Result<bool> {
+    eprint!("{}", question);
+    io::stderr().flush()?;
+
+    // One byte at a time, so nothing after the answer is used up.
+    let mut input = ManuallyDrop::new(unsafe { fs::File::from_raw_fd(0) });
+    let mut answer = Vec::new();
+    let mut byte = [0u8; 1];
+    while input.read(&mut byte)? == 1 && byte[0] != b'\n' {
+        answer.push(byte[0]);
+    }
+    Ok(matches!(answer.first(), Some(b'y' | b'Y')))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory under the system temp directory for one test.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("0-shell-overwrite-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn policy(backup: Backup) -> OverwritePolicy {
+        OverwritePolicy { backup, suffix: "~".to_string(), ..OverwritePolicy::new("cp") }
+    }
+
+    #[test]
+    fn numbered_backups_take_the_next_number() {
+        let dir = scratch("numbered");
+        let dst = dir.join("file");
+        assert_eq!(policy(Backup::Numbered).backup_name(&dst).unwrap(), Some(dir.join("file.~1~")));
+
+        for name in ["file.~1~", "file.~3~", "file.~x~", "other.~9~", "file~"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        assert_eq!(policy(Backup::Numbered).backup_name(&dst).unwrap(), Some(dir.join("file.~4~")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn existing_backups_fall_back_to_simple() {
+        let dir = scratch("existing");
+        let dst = dir.join("file");
+        assert_eq!(policy(Backup::Existing).backup_name(&dst).unwrap(), Some(dir.join("file~")));
+
+        fs::write(dir.join("file.~2~"), "").unwrap();
+        assert_eq!(policy(Backup::Existing).backup_name(&dst).unwrap(), Some(dir.join("file.~3~")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn simple_backups_use_the_suffix() {
+        let dir = scratch("simple");
+        let custom = OverwritePolicy { suffix: ".bak".to_string(), ..policy(Backup::Simple) };
+        assert_eq!(custom.backup_name(&dir.join("file")).unwrap(), Some(dir.join("file.bak")));
+        assert_eq!(policy(Backup::None).backup_name(&dir.join("file")).unwrap(), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn allows_moves_the_destination_aside() {
+        let dir = scratch("allows");
+        let (src, dst) = (dir.join("src"), dir.join("dst"));
+        fs::write(&src, "new").unwrap();
+        fs::write(&dst, "old").unwrap();
+        let metadata = fs::metadata(&src).unwrap();
+
+        assert!(policy(Backup::Numbered).allows(&metadata, &dst).unwrap());
+        assert!(!dst.exists());
+        assert_eq!(fs::read_to_string(dir.join("dst.~1~")).unwrap(), "old");
+
+        fs::write(&dst, "old").unwrap();
+        let never = OverwritePolicy { clobber: Clobber::Never, ..policy(Backup::Numbered) };
+        assert!(!never.allows(&metadata, &dst).unwrap());
+        assert!(!dir.join("dst.~2~").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
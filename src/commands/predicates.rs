@@ -0,0 +1,59 @@
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{ MetadataExt, PermissionsExt };
+use std::path::Path;
+use std::time::SystemTime;
+
+// Questions about a file's metadata that both `ls` (for indicators,
+// colours and the mode string) and `test` (for its file operators) ask,
+// so the two always agree.
+
+/// Whether any of the execute bits is set.
+pub fn is_executable(metadata: &fs::Metadata) -> bool {
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+pub fn is_setuid(metadata: &fs::Metadata) -> bool {
+    metadata.permissions().mode() & 0o4000 != 0
+}
+
+pub fn is_setgid(metadata: &fs::Metadata) -> bool {
+    metadata.permissions().mode() & 0o2000 != 0
+}
+
+pub fn is_sticky(metadata: &fs::Metadata) -> bool {
+    metadata.permissions().mode() & 0o1000 != 0
+}
+
+pub fn is_other_writable(metadata: &fs::Metadata) -> bool {
+    metadata.permissions().mode() & 0o002 != 0
+}
+
+pub fn owned_by_effective_user(metadata: &fs::Metadata) -> bool {
+    metadata.uid() == unsafe { libc::geteuid() }
+}
+
+pub fn owned_by_effective_group(metadata: &fs::Metadata) -> bool {
+    metadata.gid() == unsafe { libc::getegid() }
+}
+
+/// Whether `a` and `b` are the same file, as device and inode say.
+pub fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+/// The modification time of what `path` resolves to, if it exists.
+pub fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Whether the current user may access `path` as `mode` (`libc::R_OK`,
+/// `W_OK` or `X_OK`), which the mode bits alone cannot tell for root or
+/// under ACLs.
+pub fn accessible(path: &Path, mode: libc::c_int) -> bool {
+    match CString::new(path.as_os_str().as_bytes()) {
+        Ok(c_path) => unsafe { libc::access(c_path.as_ptr(), mode) == 0 },
+        Err(_) => false,
+    }
+}
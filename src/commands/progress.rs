@@ -0,0 +1,156 @@
+use crate::commands::columns;
+use crate::commands::filesystem::human_size;
+use crate::commands::quoting::{ self, QuotingStyle };
+use std::io::{ self, Write };
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::time::{ Duration, Instant };
+
+/// How often the status line is redrawn at most.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The `--progress` status line of `cp`: bytes and files done out of the
+/// totals found by a scan beforehand, throughput, time left and the file
+/// being copied, redrawn in place on stderr.
+#[derive(Debug)]
+pub struct Progress {
+    total_bytes: u64,
+    total_files: u64,
+    bytes: AtomicU64,
+    files: AtomicU64,
+    started: Instant,
+    line: Mutex<StatusLine>,
+}
+
+#[derive(Debug)]
+struct StatusLine {
+    current: String,
+    drawn_at: Option<Instant>,
+    /// Whether the cursor is at the end of a drawn line.
+    visible: bool,
+}
+
+impl Progress {
+    pub fn new(total_bytes: u64, total_files: u64) -> Self {
+        Progress {
+            total_bytes,
+            total_files,
+            bytes: AtomicU64::new(0),
+            files: AtomicU64::new(0),
+            started: Instant::now(),
+            line: Mutex::new(StatusLine { current: String::new(), drawn_at: None, visible: false }),
+        }
+    }
+
+    pub fn start_file(&self, path: &Path) {
+        let mut line = self.line.lock().unwrap();
+        line.current = quoting::quote(path.as_os_str(), QuotingStyle::Literal);
+        self.draw(&mut line, false);
+    }
+
+    pub fn add(&self, bytes: u64) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.draw(&mut self.line.lock().unwrap(), false);
+    }
+
+    /// Counts a file as done. `unseen` is the part of its size that was
+    /// not reported through `add`, because it was skipped or copied in one
+    /// go.
+    pub fn finish_file(&self, unseen: u64) {
+        self.bytes.fetch_add(unseen, Ordering::Relaxed);
+        self.files.fetch_add(1, Ordering::Relaxed);
+        self.draw(&mut self.line.lock().unwrap(), false);
+    }
+
+    /// Erases the status line so other output can be printed; it comes
+    /// back on the next update.
+    pub fn clear(&self) {
+        let mut line = self.line.lock().unwrap();
+        if line.visible {
+            eprint!("\r\x1b[K");
+            line.visible = false;
+        }
+    }
+
+    /// Draws the final state and leaves it on screen.
+    pub fn finish(&self) {
+        let mut line = self.line.lock().unwrap();
+        line.current.clear();
+        self.draw(&mut line, true);
+        if line.visible {
+            eprintln!();
+            line.visible = false;
+        }
+    }
+
+    fn draw(&self, line: &mut StatusLine, force: bool) {
+        let now = Instant::now();
+        if !force && line.drawn_at.is_some_and(|at| now.duration_since(at) < REDRAW_INTERVAL) {
+            return;
+        }
+        line.drawn_at = Some(now);
+
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        let files = self.files.load(Ordering::Relaxed);
+        let elapsed = now.duration_since(self.started).as_secs_f64();
+        let rate = if elapsed > 0.0 { bytes as f64 / elapsed } else { 0.0 };
+        let percent = bytes.saturating_mul(100).checked_div(self.total_bytes).map_or(100, |percent| percent.min(100));
+        let eta = if rate > 0.0 {
+            format_duration(self.total_bytes.saturating_sub(bytes) as f64 / rate)
+        } else {
+            "--:--".to_string()
+        };
+
+        let status = format!(
+            "{:>3}% {}/{} {}/s ETA {} [{}/{} files]",
+            percent,
+            human_size(bytes, 1024),
+            human_size(self.total_bytes, 1024),
+            human_size(rate as u64, 1024),
+            eta,
+            files,
+            self.total_files
+        );
+        let room = columns::terminal_width().saturating_sub(status.len() + 2);
+        let text = if line.current.is_empty() || room < 4 {
+            status
+        } else {
+            format!("{}  {}", status, fit(&line.current, room))
+        };
+
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "\r{}\x1b[K", text);
+        let _ = stderr.flush();
+        line.visible = true;
+    }
+}
+
+/// The end of `name`, which is the part that changes from file to file,
+/// cut to `width` columns.
+fn fit(name: &str, width: usize) -> String {
+    if columns::display_width(name) <= width {
+        return name.to_string();
+    }
+    let mut kept = Vec::new();
+    let mut used = 3;
+    for c in name.chars().rev() {
+        used += columns::display_width(&c.to_string());
+        if used > width {
+            break;
+        }
+        kept.push(c);
+    }
+    let tail: String = kept.into_iter().rev().collect();
+    format!("...{}", tail)
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
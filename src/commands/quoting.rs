@@ -0,0 +1,134 @@
+use crate::encoding;
+use std::ffi::OsStr;
+
+/// How a file name is written out by `ls`. Names are bytes and may hold
+/// anything but `/` and NUL, so every style other than `Literal` shows
+/// them in a form that can be read back exactly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuotingStyle {
+    /// `-N`: as is, with control characters and invalid bytes shown as `?`.
+    Literal,
+    /// `-b`: C escapes without quotes; spaces are escaped too.
+    Escape,
+    /// `-Q`: in double quotes, with C escapes.
+    C,
+    /// Single-quoted when the shell would need it, `?` for unprintables.
+    Shell,
+    ShellAlways,
+    /// Like `Shell`, but unprintables are written as `$'\ooo'`.
+    ShellEscape,
+    ShellEscapeAlways,
+}
+
+impl QuotingStyle {
+    pub fn parse(word: &str) -> Option<Self> {
+        match word {
+            "literal" => Some(QuotingStyle::Literal),
+            "escape" => Some(QuotingStyle::Escape),
+            "c" => Some(QuotingStyle::C),
+            "shell" => Some(QuotingStyle::Shell),
+            "shell-always" => Some(QuotingStyle::ShellAlways),
+            "shell-escape" => Some(QuotingStyle::ShellEscape),
+            "shell-escape-always" => Some(QuotingStyle::ShellEscapeAlways),
+            _ => None,
+        }
+    }
+}
+
+/// Quotes a file name for display. The result is always valid UTF-8 made
+/// of printable characters.
+pub fn quote(name: &OsStr, style: QuotingStyle) -> String {
+    let text = encoding::decode_os(name);
+    match style {
+        QuotingStyle::Literal => text.chars().map(|c| if printable(c) { c } else { '?' }).collect(),
+        QuotingStyle::Escape => c_escape(&text, false),
+        QuotingStyle::C => format!("\"{}\"", c_escape(&text, true)),
+        QuotingStyle::Shell | QuotingStyle::ShellAlways => {
+            let shown: String = text.chars().map(|c| if printable(c) { c } else { '?' }).collect();
+            shell_quote(&shown, style == QuotingStyle::ShellAlways)
+        }
+        QuotingStyle::ShellEscape | QuotingStyle::ShellEscapeAlways => {
+            let always = style == QuotingStyle::ShellEscapeAlways;
+            if text.chars().all(printable) {
+                return shell_quote(&text, always);
+            }
+            // Printable runs go in single quotes and everything else in
+            // `$'...'`, the two written next to each other.
+            let mut out = String::new();
+            let mut run = String::new();
+            for c in text.chars() {
+                if printable(c) {
+                    run.push(c);
+                    continue;
+                }
+                if !run.is_empty() {
+                    out.push_str(&single_quote(&run));
+                    run.clear();
+                }
+                out.push_str("$'");
+                out.push_str(&escape_char(c));
+                out.push('\'');
+            }
+            if !run.is_empty() {
+                out.push_str(&single_quote(&run));
+            }
+            out
+        }
+    }
+}
+
+fn printable(c: char) -> bool {
+    encoding::escaped_byte(c).is_none() && !c.is_control()
+}
+
+/// `\n`-style escapes for unprintables, octal for the rest. Under `-b` a
+/// space is escaped so that names can be told apart in columns.
+fn c_escape(text: &str, quoted: bool) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' if quoted => out.push_str("\\\""),
+            ' ' if !quoted => out.push_str("\\ "),
+            c if printable(c) => out.push(c),
+            c => out.push_str(&escape_char(c)),
+        }
+    }
+    out
+}
+
+fn escape_char(c: char) -> String {
+    match c {
+        '\x07' => "\\a".to_string(),
+        '\x08' => "\\b".to_string(),
+        '\x0c' => "\\f".to_string(),
+        '\n' => "\\n".to_string(),
+        '\r' => "\\r".to_string(),
+        '\t' => "\\t".to_string(),
+        '\x0b' => "\\v".to_string(),
+        '\x1b' => "\\e".to_string(),
+        c => match encoding::escaped_byte(c) {
+            Some(byte) => format!("\\{:03o}", byte),
+            None => c.encode_utf8(&mut [0; 4]).bytes().map(|byte| format!("\\{:03o}", byte)).collect(),
+        },
+    }
+}
+
+/// Quotes `text` for a POSIX shell if it holds anything the shell treats
+/// specially, preferring double quotes for names with a `'` in them.
+fn shell_quote(text: &str, always: bool) -> String {
+    let plain = !text.is_empty()
+        && !text.starts_with('~')
+        && text.chars().all(|c| c.is_alphanumeric() || "-_./:,+@%^=".contains(c));
+    if plain && !always {
+        return text.to_string();
+    }
+    if text.contains('\'') && !text.contains(['"', '$', '`', '\\', '!']) {
+        return format!("\"{}\"", text);
+    }
+    single_quote(text)
+}
+
+fn single_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
@@ -0,0 +1,246 @@
+use std::ffi::{ CString, OsStr, OsString };
+use std::io;
+use std::os::unix::ffi::{ OsStrExt, OsStringExt };
+use std::path::Path;
+
+pub const ACL_ACCESS: &str = "system.posix_acl_access";
+pub const ACL_DEFAULT: &str = "system.posix_acl_default";
+pub const SELINUX_CONTEXT: &str = "security.selinux";
+
+/// Version of the ACL format the kernel uses in the xattrs above.
+const ACL_XATTR_VERSION: u32 = 2;
+/// `id` of entries that do not name a user or group.
+const ACL_UNDEFINED_ID: u32 = u32::MAX;
+
+/// One entry of a POSIX ACL, as stored in `system.posix_acl_*`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AclEntry {
+    pub tag: AclTag,
+    /// `rwx` bits, 4/2/1.
+    pub perm: u16,
+    /// The uid or gid for `User` and `Group` entries.
+    pub id: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AclTag {
+    UserObj,
+    User,
+    GroupObj,
+    Group,
+    Mask,
+    Other,
+}
+
+/// What `ls -l` appends to the mode string: `+` for a file with an ACL
+/// beyond its mode bits, else `.` for one with an SELinux context. Other
+/// attributes are not marked.
+pub fn mode_marker(path: &Path, follow: bool) -> Option<char> {
+    let names = list(path, follow).unwrap_or_default();
+    let has = |wanted: &str| names.iter().any(|name| name == wanted);
+    if has(ACL_DEFAULT) {
+        return Some('+');
+    }
+    if has(ACL_ACCESS)
+        && let Ok(Some(value)) = get(path, OsStr::new(ACL_ACCESS), follow)
+        && parse_acl(&value).is_some_and(|acl| !is_minimal(&acl))
+    {
+        return Some('+');
+    }
+    if has(SELINUX_CONTEXT) { Some('.') } else { None }
+}
+
+/// The names of the extended attributes on `path`. Filesystems without
+/// xattr support have none. `follow` reads through a symlink.
+pub fn list(path: &Path, follow: bool) -> io::Result<Vec<OsString>> {
+    let c_path = c_string(path.as_os_str())?;
+    let list_into = |buffer: &mut [u8]| unsafe {
+        let data = if buffer.is_empty() { std::ptr::null_mut() } else { buffer.as_mut_ptr() as *mut libc::c_char };
+        if follow {
+            libc::listxattr(c_path.as_ptr(), data, buffer.len())
+        } else {
+            libc::llistxattr(c_path.as_ptr(), data, buffer.len())
+        }
+    };
+    let buffer = match read_growing(list_into) {
+        Ok(buffer) => buffer,
+        Err(e) if e.raw_os_error() == Some(libc::ENOTSUP) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(buffer
+        .split(|&byte| byte == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| OsString::from_vec(name.to_vec()))
+        .collect())
+}
+
+/// The value of one attribute, or `None` if the file does not have it.
+pub fn get(path: &Path, name: &OsStr, follow: bool) -> io::Result<Option<Vec<u8>>> {
+    let c_path = c_string(path.as_os_str())?;
+    let c_name = c_string(name)?;
+    let get_into = |buffer: &mut [u8]| unsafe {
+        let data = if buffer.is_empty() { std::ptr::null_mut() } else { buffer.as_mut_ptr() as *mut libc::c_void };
+        if follow {
+            libc::getxattr(c_path.as_ptr(), c_name.as_ptr(), data, buffer.len())
+        } else {
+            libc::lgetxattr(c_path.as_ptr(), c_name.as_ptr(), data, buffer.len())
+        }
+    };
+    match read_growing(get_into) {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if matches!(e.raw_os_error(), Some(libc::ENODATA | libc::ENOTSUP)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Sets one attribute, creating or replacing it.
+pub fn set(path: &Path, name: &OsStr, value: &[u8], follow: bool) -> io::Result<()> {
+    let c_path = c_string(path.as_os_str())?;
+    let c_name = c_string(name)?;
+    let data = value.as_ptr() as *const libc::c_void;
+    let rc = unsafe {
+        if follow {
+            libc::setxattr(c_path.as_ptr(), c_name.as_ptr(), data, value.len(), 0)
+        } else {
+            libc::lsetxattr(c_path.as_ptr(), c_name.as_ptr(), data, value.len(), 0)
+        }
+    };
+    if rc == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+}
+
+/// Runs a `*xattr` call that fails with `ERANGE` when the buffer is too
+/// small, asking for the size and retrying. The size can change between
+/// the calls, so this loops until a read fits.
+fn read_growing(mut call: impl FnMut(&mut [u8]) -> isize) -> io::Result<Vec<u8>> {
+    let mut buffer = vec![0u8; 1024];
+    loop {
+        let len = call(&mut buffer);
+        if len >= 0 {
+            buffer.truncate(len as usize);
+            return Ok(buffer);
+        }
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() != Some(libc::ERANGE) {
+            return Err(error);
+        }
+        let needed = call(&mut []);
+        if needed < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        buffer.resize((needed as usize).max(buffer.len() * 2), 0);
+    }
+}
+
+fn c_string(text: &OsStr) -> io::Result<CString> {
+    CString::new(text.as_bytes()).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))
+}
+
+/// Decodes a `system.posix_acl_*` value: a little-endian version word,
+/// then 8-byte entries of tag, permissions and id.
+pub fn parse_acl(value: &[u8]) -> Option<Vec<AclEntry>> {
+    let (header, body) = value.split_at_checked(4)?;
+    if u32::from_le_bytes(header.try_into().ok()?) != ACL_XATTR_VERSION || body.len() % 8 != 0 {
+        return None;
+    }
+    body.chunks_exact(8)
+        .map(|entry| {
+            let tag = match u16::from_le_bytes([entry[0], entry[1]]) {
+                0x01 => AclTag::UserObj,
+                0x02 => AclTag::User,
+                0x04 => AclTag::GroupObj,
+                0x08 => AclTag::Group,
+                0x10 => AclTag::Mask,
+                0x20 => AclTag::Other,
+                _ => return None,
+            };
+            let perm = u16::from_le_bytes([entry[2], entry[3]]) & 0o7;
+            let id = u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]);
+            Some(AclEntry { tag, perm, id: Some(id).filter(|&id| id != ACL_UNDEFINED_ID) })
+        })
+        .collect()
+}
+
+/// The ACL every file has through its mode bits.
+pub fn acl_from_mode(mode: u32) -> Vec<AclEntry> {
+    let entry = |tag, shift: u32| AclEntry { tag, perm: ((mode >> shift) & 0o7) as u16, id: None };
+    vec![entry(AclTag::UserObj, 6), entry(AclTag::GroupObj, 3), entry(AclTag::Other, 0)]
+}
+
+/// True for an ACL that says no more than the mode bits do.
+pub fn is_minimal(acl: &[AclEntry]) -> bool {
+    acl.iter().all(|entry| matches!(entry.tag, AclTag::UserObj | AclTag::GroupObj | AclTag::Other))
+}
+
+pub fn perm_string(perm: u16) -> String {
+    [(4, 'r'), (2, 'w'), (1, 'x')]
+        .iter()
+        .map(|&(bit, c)| if perm & bit != 0 { c } else { '-' })
+        .collect()
+}
+
+/// An attribute value for display: quoted text when it is printable, with
+/// the NUL that often ends it dropped, otherwise hex.
+pub fn format_value(value: &[u8]) -> String {
+    let text = value.strip_suffix(b"\0").unwrap_or(value);
+    match std::str::from_utf8(text) {
+        Ok(text) if !text.chars().any(char::is_control) => format!("{:?}", text),
+        _ => {
+            let hex: String = value.iter().map(|byte| format!("{:02x}", byte)).collect();
+            format!("0x{}", hex)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `system.posix_acl_access` value built the way the kernel stores it.
+    fn blob(entries: &[(u16, u16, u32)]) -> Vec<u8> {
+        let mut value = ACL_XATTR_VERSION.to_le_bytes().to_vec();
+        for &(tag, perm, id) in entries {
+            value.extend_from_slice(&tag.to_le_bytes());
+            value.extend_from_slice(&perm.to_le_bytes());
+            value.extend_from_slice(&id.to_le_bytes());
+        }
+        value
+    }
+
+    #[test]
+    fn minimal_acl_matches_the_mode_bits() {
+        let value = blob(&[(0x01, 6, ACL_UNDEFINED_ID), (0x04, 4, ACL_UNDEFINED_ID), (0x20, 4, ACL_UNDEFINED_ID)]);
+        let acl = parse_acl(&value).unwrap();
+        assert_eq!(acl, acl_from_mode(0o644));
+        assert!(is_minimal(&acl));
+    }
+
+    #[test]
+    fn extended_acl_names_users_and_a_mask() {
+        let value = blob(&[
+            (0x01, 7, ACL_UNDEFINED_ID),
+            (0x02, 5, 1000),
+            (0x04, 5, ACL_UNDEFINED_ID),
+            (0x08, 6, 100),
+            (0x10, 7, ACL_UNDEFINED_ID),
+            (0x20, 0, ACL_UNDEFINED_ID),
+        ]);
+        let acl = parse_acl(&value).unwrap();
+        assert_eq!(acl.len(), 6);
+        assert_eq!(acl[1], AclEntry { tag: AclTag::User, perm: 5, id: Some(1000) });
+        assert_eq!(acl[3], AclEntry { tag: AclTag::Group, perm: 6, id: Some(100) });
+        assert_eq!(acl[4], AclEntry { tag: AclTag::Mask, perm: 7, id: None });
+        assert!(!is_minimal(&acl));
+        assert_eq!(perm_string(acl[1].perm), "r-x");
+    }
+
+    #[test]
+    fn malformed_values_are_rejected() {
+        assert_eq!(parse_acl(&[]), None);
+        let mut wrong_version = blob(&[(0x01, 6, ACL_UNDEFINED_ID)]);
+        wrong_version[0] = 1;
+        assert_eq!(parse_acl(&wrong_version), None);
+        let truncated = blob(&[(0x01, 6, ACL_UNDEFINED_ID)]);
+        assert_eq!(parse_acl(&truncated[..truncated.len() - 1]), None);
+        assert_eq!(parse_acl(&blob(&[(0x40, 6, ACL_UNDEFINED_ID)])), None);
+    }
+}
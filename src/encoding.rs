@@ -0,0 +1,117 @@
+use std::ffi::{ OsStr, OsString };
+use std::os::unix::ffi::{ OsStrExt, OsStringExt };
+
+// File names are bytes, but the parser works on `str`. Input is decoded so
+// that every byte which is not part of valid UTF-8 becomes a character from
+// a private-use range, and words are encoded back to the same bytes when
+// they become arguments. A name that really holds one of those characters
+// has its UTF-8 bytes escaped one by one instead, so that it too comes back
+// unchanged. Nothing is lost on the way through.
+
+/// `ESCAPE_BASE + b` stands for the raw byte `b` (0x80..=0xff).
+const ESCAPE_BASE: u32 = 0x10ff00;
+
+pub fn decode(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len());
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                push_valid(&mut text, valid);
+                break;
+            }
+            Err(e) => {
+                let (valid, after) = rest.split_at(e.valid_up_to());
+                push_valid(&mut text, std::str::from_utf8(valid).unwrap_or_default());
+                let bad = e.error_len().unwrap_or(after.len());
+                for &byte in &after[..bad] {
+                    text.push(escape_byte(byte));
+                }
+                rest = &after[bad..];
+            }
+        }
+    }
+    text
+}
+
+/// Appends valid UTF-8, escaping the bytes of any character that would be
+/// taken for an escaped byte.
+fn push_valid(text: &mut String, valid: &str) {
+    for c in valid.chars() {
+        if escaped_byte(c).is_some() {
+            for &byte in c.encode_utf8(&mut [0; 4]).as_bytes() {
+                text.push(escape_byte(byte));
+            }
+        } else {
+            text.push(c);
+        }
+    }
+}
+
+pub fn decode_os(text: &OsStr) -> String {
+    decode(text.as_bytes())
+}
+
+pub fn encode(text: &str) -> OsString {
+    OsString::from_vec(encode_bytes(text))
+}
+
+pub fn encode_bytes(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        match escaped_byte(c) {
+            Some(byte) => bytes.push(byte),
+            None => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    bytes
+}
+
+/// The raw byte a decoded character stands for, if it is one.
+pub fn escaped_byte(c: char) -> Option<u8> {
+    let code = c as u32;
+    if (ESCAPE_BASE + 0x80..=ESCAPE_BASE + 0xff).contains(&code) {
+        Some((code - ESCAPE_BASE) as u8)
+    } else {
+        None
+    }
+}
+
+fn escape_byte(byte: u8) -> char {
+    // Invalid UTF-8 never starts with an ASCII byte.
+    char::from_u32(ESCAPE_BASE + byte as u32).unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(bytes: &[u8]) {
+        assert_eq!(encode_bytes(&decode(bytes)), bytes, "{:?}", bytes);
+    }
+
+    #[test]
+    fn invalid_bytes_round_trip() {
+        for byte in 0x80..=0xffu8 {
+            round_trip(&[byte]);
+            round_trip(&[b'a', byte, b'z']);
+        }
+        round_trip(b"caf\xe9\xff\xfe.txt");
+    }
+
+    #[test]
+    fn escape_range_characters_round_trip() {
+        for code in ESCAPE_BASE + 0x80..=ESCAPE_BASE + 0xff {
+            let c = char::from_u32(code).unwrap();
+            let name = format!("x{}y", c);
+            round_trip(name.as_bytes());
+        }
+    }
+
+    #[test]
+    fn valid_text_is_unchanged() {
+        let text = "plain, ünïcödé and \u{10ff7f}";
+        assert_eq!(decode(text.as_bytes()), text);
+        assert_eq!(encode(text), OsStr::new(text));
+    }
+}
@@ -10,7 +10,11 @@ pub enum ShellError {
     ExecutionError(String),
     FileSystemError(String),
     IncompleteInput(char), // For unclosed quotes
+    IncompleteHereDoc(String), // Here-document still waiting for its delimiter
     InvalidOption(String),
+    ArithmeticError(String),
+    ExitStatus(i32), // Non-zero status with nothing to report, e.g. `let 0`
+    Exit(Option<i32>), // `exit` was run; `None` keeps the last status
 }
 
 impl fmt::Display for ShellError {
@@ -22,7 +26,23 @@ impl fmt::Display for ShellError {
             ShellError::ExecutionError(msg) => write!(f, "Execution error: {}", msg),
             ShellError::FileSystemError(msg) => write!(f, "File system error: {}", msg),
             ShellError::IncompleteInput(ch) => write!(f, "Incomplete input: unclosed quote '{}'", ch),
+            ShellError::IncompleteHereDoc(delim) => write!(f, "Incomplete input: here-document delimited by '{}'", delim),
             ShellError::InvalidOption(msg) => write!(f, "Invalid option: {}", msg),
+            ShellError::ArithmeticError(msg) => write!(f, "Arithmetic error: {}", msg),
+            ShellError::ExitStatus(code) => write!(f, "Exit status {}", code),
+            ShellError::Exit(_) => write!(f, "exit"),
+        }
+    }
+}
+
+impl ShellError {
+    /// The exit status a failing command reports through `$?`.
+    pub fn exit_status(&self) -> i32 {
+        match self {
+            ShellError::CommandNotFound(_) => 127,
+            ShellError::ExitStatus(code) => *code,
+            ShellError::Exit(code) => code.unwrap_or(0),
+            _ => 1,
         }
     }
 }
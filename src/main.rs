@@ -3,14 +3,21 @@ mod shell;
 mod commands;
 mod parser;
 mod error;
+mod arithmetic;
+mod redirect;
+mod trap;
+mod encoding;
 
 use shell::Shell;
 
 fn main() {
     let mut shell = Shell::new();
     
-    if let Err(e) = shell.run() {
-        eprintln!("Error: {}", e);
-        std::process::exit(1);
+    match shell.run() {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
     }
 }
\ No newline at end of file
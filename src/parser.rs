@@ -1,10 +1,127 @@
 use crate::error::ShellError;
+use std::ffi::OsString;
 
+/// A fully expanded command, ready to be dispatched to the registry.
 #[derive(Debug, Clone, PartialEq)]
-
 pub struct Command {
     pub name: String,
-    pub args: Vec<String>,
+    /// Arguments are handed over as bytes, since file names need not be
+    /// valid UTF-8.
+    pub args: Vec<OsString>,
+}
+
+/// One piece of a word as it was written, before expansion.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WordPart {
+    /// Unquoted text. A leading `~` is subject to tilde expansion.
+    Bare(String),
+    /// Quoted or backslash-escaped text, used as is.
+    Quoted(String),
+    /// `$NAME`, `${NAME}` or a special parameter such as `$?`.
+    Parameter(String),
+    /// A parameter expanded inside double quotes. Its value is never treated
+    /// as a pattern.
+    QuotedParameter(String),
+    /// The expression inside `$(( ... ))`.
+    Arithmetic(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+}
+
+impl Word {
+    /// Splits `NAME=value` into the name and the unexpanded value.
+    pub fn as_assignment(&self) -> Option<(String, Word)> {
+        let Some(WordPart::Bare(first)) = self.parts.first() else {
+            return None;
+        };
+        let (name, rest) = first.split_once('=')?;
+        if !is_valid_parameter(name) || name.chars().all(|c| c.is_ascii_digit()) || name == "?" || name == "$" {
+            return None;
+        }
+
+        let mut value = Word::default();
+        if !rest.is_empty() {
+            value.parts.push(WordPart::Bare(rest.to_string()));
+        }
+        value.parts.extend(self.parts[1..].iter().cloned());
+        Some((name.to_string(), value))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RedirectKind {
+    /// `< file`
+    Input(Word),
+    /// `> file`
+    Output(Word),
+    /// `>| file`, which overwrites even with `set -o noclobber`
+    Clobber(Word),
+    /// `>> file`
+    Append(Word),
+    /// `n>&m`, `n<&m`
+    Duplicate(i32),
+    /// `<<DELIM` / `<<-DELIM`. When the delimiter was quoted the body is a
+    /// single literal part and no expansion takes place.
+    HereDoc(Word),
+    /// `<<< word`
+    HereString(Word),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    pub fd: i32,
+    pub kind: RedirectKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SimpleCommand {
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+}
+
+/// A single command. Words are kept unexpanded so that variables are looked
+/// up when the statement runs, not when it is read.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Simple(SimpleCommand),
+    /// `(( expression ))`
+    Arithmetic(String),
+    /// `[[ expression ]]`
+    Conditional(Vec<CondToken>),
+    /// `( list )`, run with its own copy of the shell state.
+    Subshell(List, Vec<Redirect>),
+    /// `{ list; }`, run in the current shell.
+    Group(List, Vec<Redirect>),
+}
+
+/// A token between `[[` and `]]`. Operators that would otherwise be shell
+/// syntax are kept apart from ordinary words.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CondToken {
+    Word(Word),
+    Operator(&'static str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connector {
+    And,
+    Or,
+}
+
+/// Statements joined by `&&` and `||`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AndOr {
+    pub first: Statement,
+    pub rest: Vec<(Connector, Statement)>,
+}
+
+/// A sequence of and-or chains separated by `;` or newlines.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct List {
+    pub items: Vec<AndOr>,
 }
 
 pub struct CommandParser;
@@ -14,143 +131,811 @@ impl CommandParser {
         Self
     }
 
-    pub fn parse(&self, input: &str) -> Result<Option<Command>, ShellError> {
+    pub fn parse(&self, input: &str) -> Result<Option<List>, ShellError> {
         let trimmed = input.trim();
-    
-    
+
         if trimmed.is_empty() {
             return Ok(None);
         }
-    
-        let mut parts = Vec::new();
-        let mut current_part = String::new();
-        let mut in_quotes = false;
-        let mut quote_char = '\0';
-        let mut token_had_quotes = false;
-        let mut chars = trimmed.chars();
-    
-        while let Some(ch) = chars.next() {
+
+        let mut scanner = Scanner::new(trimmed);
+        let mut list = scanner.parse_list(None)?;
+        if let Some(ch) = scanner.peek() {
+            return Err(unexpected_token(ch));
+        }
+        scanner.finish_heredocs()?;
+
+        let mut bodies = std::mem::take(&mut scanner.heredoc_bodies).into_iter();
+        fill_heredocs(&mut list, &mut bodies);
+
+        if list.items.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(list))
+    }
+}
+
+/// Here-document bodies are read after the line holding their operators, so
+/// they are attached to the tree once parsing is done, in textual order.
+fn fill_heredocs(list: &mut List, bodies: &mut impl Iterator<Item = Word>) {
+    for item in &mut list.items {
+        let rest = item.rest.iter_mut().map(|(_, statement)| statement);
+        for statement in std::iter::once(&mut item.first).chain(rest) {
+            let redirects = match statement {
+                Statement::Simple(command) => &mut command.redirects,
+                Statement::Subshell(body, redirects) | Statement::Group(body, redirects) => {
+                    fill_heredocs(body, bodies);
+                    redirects
+                }
+                Statement::Arithmetic(_) | Statement::Conditional(_) => continue,
+            };
+            for redirect in redirects {
+                if let RedirectKind::HereDoc(body) = &mut redirect.kind {
+                    *body = bodies.next().unwrap_or_default();
+                }
+            }
+        }
+    }
+}
+
+fn unexpected_token(ch: char) -> ShellError {
+    ShellError::ParseError(format!("syntax error near unexpected token '{}'", ch))
+}
+
+struct PendingHereDoc {
+    delimiter: String,
+    strip_tabs: bool,
+    quoted: bool,
+}
+
+struct Scanner {
+    chars: Vec<char>,
+    pos: usize,
+    pending_heredocs: Vec<PendingHereDoc>,
+    heredoc_bodies: Vec<Word>,
+}
+
+impl Scanner {
+    fn new(input: &str) -> Self {
+        Self {
+            chars: input.chars().collect(),
+            pos: 0,
+            pending_heredocs: Vec::new(),
+            heredoc_bodies: Vec::new(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let ch = self.peek();
+        if ch.is_some() {
+            self.pos += 1;
+        }
+        ch
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        s.chars().enumerate().all(|(i, c)| self.peek_at(i) == Some(c))
+    }
+
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.pos += 1;
+        }
+    }
+
+    /// Skips blanks and newlines. Here-document bodies start on the line
+    /// after their operator, so they are collected at each newline.
+    fn skip_blanks(&mut self) -> Result<(), ShellError> {
+        while let Some(ch) = self.peek() {
             match ch {
-                '"' | '\'' => {
-                    if !in_quotes {
-                        in_quotes = true;
-                        quote_char = ch;
-                        token_had_quotes = true;
-                    } else if ch == quote_char {
-                        in_quotes = false;
-                        quote_char = '\0';
-                    } else {
-                        current_part.push(ch);
+                ' ' | '\t' => self.pos += 1,
+                '\n' => {
+                    self.pos += 1;
+                    self.read_heredoc_bodies()?;
+                }
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the next word is exactly `word`, as a reserved word that ends
+    /// at a blank, an operator or the end of input.
+    fn at_reserved(&self, word: &str) -> bool {
+        self.starts_with(word)
+            && self.peek_at(word.chars().count())
+                .is_none_or(|c| is_blank(c) || matches!(c, ';' | '&' | '|' | ')' | '<' | '>'))
+    }
+
+    /// Parses and-or chains up to the end of input or, inside a group, up to
+    /// the closing `)` or `}` (which is left for the caller).
+    fn parse_list(&mut self, closer: Option<char>) -> Result<List, ShellError> {
+        let mut list = List::default();
+        loop {
+            self.skip_blanks()?;
+            match self.peek() {
+                None => break,
+                Some(')') if closer == Some(')') => break,
+                Some('}') if closer == Some('}') && self.at_reserved("}") => break,
+                Some(';') => return Err(unexpected_token(';')),
+                _ => {}
+            }
+
+            list.items.push(self.parse_and_or()?);
+
+            self.skip_spaces();
+            match self.peek() {
+                Some(';') => {
+                    self.pos += 1;
+                    if self.peek() == Some(';') {
+                        return Err(ShellError::ParseError("syntax error near unexpected token ';;'".to_string()));
                     }
                 }
-                '\n' => {
-                    if in_quotes {
-                        current_part.push('\n');
-                    } else {
-                        if !current_part.is_empty() {
-                            let pushed = if !token_had_quotes {
-                                expand_tilde_word(&current_part)
-                            } else {
-                                current_part.clone()
-                            };
-                            parts.push(pushed);
-                            current_part.clear();
-                            token_had_quotes = false;
+                Some('\n') | None => {}
+                Some(')') if closer == Some(')') => {}
+                Some(ch) => return Err(unexpected_token(ch)),
+            }
+        }
+        Ok(list)
+    }
+
+    fn parse_and_or(&mut self) -> Result<AndOr, ShellError> {
+        let first = self.parse_statement()?;
+        let mut rest = Vec::new();
+        loop {
+            self.skip_spaces();
+            let connector = if self.starts_with("&&") {
+                Connector::And
+            } else if self.starts_with("||") {
+                Connector::Or
+            } else {
+                break;
+            };
+            self.pos += 2;
+            self.skip_blanks()?;
+            if self.peek().is_none() {
+                // `cmd &&` continues on the next line.
+                return Err(ShellError::IncompleteInput(if connector == Connector::And { '&' } else { '|' }));
+            }
+            rest.push((connector, self.parse_statement()?));
+        }
+        Ok(AndOr { first, rest })
+    }
+
+    fn parse_statement(&mut self) -> Result<Statement, ShellError> {
+        if self.starts_with("((") {
+            self.pos += 2;
+            let expr = self.read_arithmetic()?;
+            return Ok(Statement::Arithmetic(expr));
+        }
+
+        if self.at_reserved("[[") {
+            self.pos += 2;
+            return Ok(Statement::Conditional(self.read_conditional()?));
+        }
+
+        if self.peek() == Some('(') {
+            self.pos += 1;
+            let body = self.parse_list(Some(')'))?;
+            if self.next() != Some(')') {
+                return Err(ShellError::IncompleteInput('('));
+            }
+            if body.items.is_empty() {
+                return Err(unexpected_token(')'));
+            }
+            let redirects = self.read_trailing_redirects()?;
+            return Ok(Statement::Subshell(body, redirects));
+        }
+
+        if self.at_reserved("{") {
+            self.pos += 1;
+            let body = self.parse_list(Some('}'))?;
+            if self.next() != Some('}') {
+                return Err(ShellError::IncompleteInput('{'));
+            }
+            if body.items.is_empty() {
+                return Err(unexpected_token('}'));
+            }
+            let redirects = self.read_trailing_redirects()?;
+            return Ok(Statement::Group(body, redirects));
+        }
+
+        let mut command = SimpleCommand::default();
+        loop {
+            self.skip_spaces();
+            if let Some(redirect) = self.read_redirect()? {
+                command.redirects.push(redirect);
+                continue;
+            }
+            match self.peek() {
+                Some('(') => return Err(unexpected_token('(')),
+                Some('|') if !self.starts_with("||") => {
+                    return Err(ShellError::ParseError("pipes are not supported".to_string()));
+                }
+                Some('&') if !self.starts_with("&&") => {
+                    return Err(ShellError::ParseError("background jobs are not supported".to_string()));
+                }
+                _ => {}
+            }
+            match self.read_word()? {
+                Some(word) => command.words.push(word),
+                None => break,
+            }
+        }
+
+        if command.words.is_empty() && command.redirects.is_empty() {
+            return Err(match self.peek() {
+                Some(ch) => unexpected_token(ch),
+                None => ShellError::ParseError("syntax error: unexpected end of input".to_string()),
+            });
+        }
+        Ok(Statement::Simple(command))
+    }
+
+    fn read_trailing_redirects(&mut self) -> Result<Vec<Redirect>, ShellError> {
+        let mut redirects = Vec::new();
+        loop {
+            self.skip_spaces();
+            match self.read_redirect()? {
+                Some(redirect) => redirects.push(redirect),
+                None => return Ok(redirects),
+            }
+        }
+    }
+
+    /// Parses a redirection operator and its target, with an optional file
+    /// descriptor number in front (`2>file`).
+    fn read_redirect(&mut self) -> Result<Option<Redirect>, ShellError> {
+        let mut offset = 0;
+        while self.peek_at(offset).is_some_and(|c| c.is_ascii_digit()) {
+            offset += 1;
+        }
+        let op = match self.peek_at(offset) {
+            Some(c @ ('<' | '>')) => c,
+            _ => return Ok(None),
+        };
+
+        let fd = if offset > 0 {
+            let digits: String = self.chars[self.pos..self.pos + offset].iter().collect();
+            digits.parse().map_err(|_| ShellError::ParseError(format!("{}: bad file descriptor", digits)))?
+        } else if op == '<' {
+            0
+        } else {
+            1
+        };
+        self.pos += offset + 1;
+
+        let kind = if op == '<' && self.starts_with("<<") {
+            self.pos += 2;
+            RedirectKind::HereString(self.read_target("<<<")?)
+        } else if op == '<' && self.peek() == Some('<') {
+            self.pos += 1;
+            let strip_tabs = self.peek() == Some('-');
+            if strip_tabs {
+                self.pos += 1;
+            }
+            let word = self.read_target("<<")?;
+            let quoted = word.parts.iter().any(|p| matches!(p, WordPart::Quoted(_)));
+            let delimiter = word.parts.iter().map(|p| match p {
+                WordPart::Bare(text) | WordPart::Quoted(text) => text.clone(),
+                WordPart::Parameter(name) | WordPart::QuotedParameter(name) => format!("${}", name),
+                WordPart::Arithmetic(expr) => format!("$(({}))", expr),
+            }).collect();
+            self.pending_heredocs.push(PendingHereDoc { delimiter, strip_tabs, quoted });
+            RedirectKind::HereDoc(Word::default())
+        } else if op == '>' && self.peek() == Some('>') {
+            self.pos += 1;
+            RedirectKind::Append(self.read_target(">>")?)
+        } else if self.peek() == Some('&') {
+            self.pos += 1;
+            let mut digits = String::new();
+            while let Some(c) = self.peek().filter(|c| c.is_ascii_digit()) {
+                digits.push(c);
+                self.pos += 1;
+            }
+            let target = digits.parse().map_err(|_| ShellError::ParseError(
+                format!("syntax error near '{}&'", op)
+            ))?;
+            RedirectKind::Duplicate(target)
+        } else if op == '<' {
+            RedirectKind::Input(self.read_target("<")?)
+        } else if self.peek() == Some('|') {
+            self.pos += 1;
+            RedirectKind::Clobber(self.read_target(">|")?)
+        } else {
+            RedirectKind::Output(self.read_target(">")?)
+        };
+
+        Ok(Some(Redirect { fd, kind }))
+    }
+
+    fn read_target(&mut self, op: &str) -> Result<Word, ShellError> {
+        self.skip_spaces();
+        self.read_word()?.ok_or_else(|| ShellError::ParseError(
+            format!("syntax error near unexpected token '{}'", op)
+        ))
+    }
+
+    /// Reads the tokens of a `[[ ... ]]` expression, with the opening `[[`
+    /// already consumed, up to and including the closing `]]`.
+    fn read_conditional(&mut self) -> Result<Vec<CondToken>, ShellError> {
+        let mut tokens = Vec::new();
+        loop {
+            self.skip_blanks()?;
+            if self.at_reserved("]]") {
+                self.pos += 2;
+                return Ok(tokens);
+            }
+
+            let operator = ["&&", "||", "(", ")", "<", ">"]
+                .into_iter()
+                .find(|op| self.starts_with(op));
+            if let Some(op) = operator {
+                self.pos += op.len();
+                tokens.push(CondToken::Operator(op));
+                continue;
+            }
+
+            // The right-hand side of `=~` is a regular expression, in which
+            // parentheses, `|` and angle brackets are ordinary characters.
+            let after_regex_op = matches!(
+                tokens.last(),
+                Some(CondToken::Word(Word { parts })) if parts == &[WordPart::Bare("=~".to_string())]
+            );
+            let word = if after_regex_op {
+                self.read_word_until(is_blank)?
+            } else {
+                self.read_word_until(|ch| matches!(ch, ' ' | '\t' | '\n' | '<' | '>' | '(' | ')' | '&' | '|'))?
+            };
+            match word {
+                Some(word) => tokens.push(CondToken::Word(word)),
+                None if self.peek().is_none() => return Err(ShellError::IncompleteInput('[')),
+                None => {
+                    return Err(ShellError::ParseError(
+                        format!("syntax error in conditional expression near '{}'", self.peek().unwrap_or(' '))
+                    ));
+                }
+            }
+        }
+    }
+
+    fn finish_heredocs(&mut self) -> Result<(), ShellError> {
+        if let Some(pending) = self.pending_heredocs.first() {
+            // The operator was on the last line, so the body has not been
+            // typed yet.
+            return Err(ShellError::IncompleteHereDoc(pending.delimiter.clone()));
+        }
+        Ok(())
+    }
+
+    /// Reads the bodies of all pending here-documents, one after the other,
+    /// starting at the current position (the beginning of a line).
+    fn read_heredoc_bodies(&mut self) -> Result<(), ShellError> {
+        for pending in std::mem::take(&mut self.pending_heredocs) {
+            let mut body = String::new();
+            loop {
+                if self.peek().is_none() {
+                    return Err(ShellError::IncompleteHereDoc(pending.delimiter));
+                }
+                let mut line = String::new();
+                while let Some(ch) = self.next() {
+                    if ch == '\n' {
+                        break;
+                    }
+                    line.push(ch);
+                }
+                let line = if pending.strip_tabs { line.trim_start_matches('\t') } else { &line };
+                if line == pending.delimiter {
+                    break;
+                }
+                body.push_str(line);
+                body.push('\n');
+            }
+
+            let word = if pending.quoted {
+                Word { parts: vec![WordPart::Quoted(body)] }
+            } else {
+                Scanner::new(&body).read_heredoc_text()?
+            };
+            self.heredoc_bodies.push(word);
+        }
+        Ok(())
+    }
+
+    /// Splits an unquoted here-document body into literal text and
+    /// expansions. Only `\$`, `\\` and backslash-newline are escapes here.
+    fn read_heredoc_text(&mut self) -> Result<Word, ShellError> {
+        let mut word = Word::default();
+        let mut text = String::new();
+        while let Some(ch) = self.next() {
+            match ch {
+                '\\' => match self.next() {
+                    Some(c @ ('$' | '\\' | '`')) => text.push(c),
+                    Some('\n') => {}
+                    Some(c) => {
+                        text.push('\\');
+                        text.push(c);
+                    }
+                    None => text.push('\\'),
+                },
+                '$' => match self.read_dollar()? {
+                    Some(part) => {
+                        if !text.is_empty() {
+                            word.parts.push(WordPart::Quoted(std::mem::take(&mut text)));
                         }
+                        word.parts.push(part);
                     }
+                    None => text.push('$'),
+                },
+                _ => text.push(ch),
+            }
+        }
+        if !text.is_empty() {
+            word.parts.push(WordPart::Quoted(text));
+        }
+        Ok(word)
+    }
+
+    /// Reads one word up to the next unquoted blank or operator. Returns
+    /// `None` at the end of input.
+    fn read_word(&mut self) -> Result<Option<Word>, ShellError> {
+        self.read_word_until(|ch| matches!(ch, ' ' | '\t' | '\n' | '<' | '>' | ';' | '&' | '|' | '(' | ')'))
+    }
+
+    fn read_word_until(&mut self, is_break: fn(char) -> bool) -> Result<Option<Word>, ShellError> {
+        let mut word = Word::default();
+        let mut bare = String::new();
+        let mut had_quotes = false;
+
+        while let Some(ch) = self.peek() {
+            match ch {
+                _ if is_break(ch) => break,
+                '\'' => {
+                    self.pos += 1;
+                    flush_bare(&mut word, &mut bare);
+                    had_quotes = true;
+                    let text = self.read_single_quoted()?;
+                    word.parts.push(WordPart::Quoted(text));
+                }
+                '"' => {
+                    self.pos += 1;
+                    flush_bare(&mut word, &mut bare);
+                    had_quotes = true;
+                    self.read_double_quoted(&mut word)?;
                 }
                 '\\' => {
-                    if in_quotes {
-                        if let Some(next_ch) = chars.next() {
-                            match next_ch {
-                                'n' => current_part.push('\n'),
-                                't' => current_part.push('\t'),
-                                'r' => current_part.push('\r'),
-                                '\\' | '"' | '\'' => current_part.push(next_ch),
-                                '\n' => {
-                                    // Backslash-newline is removed (line continuation)
-                                }
-                                _ => {
-                                    current_part.push('\\');
-                                    current_part.push(next_ch);
-                                }
-                            }
-                        } else {
-                            return Err(ShellError::IncompleteInput('\\'));
+                    self.pos += 1;
+                    match self.next() {
+                        Some('\n') => {
+                            // Backslash-newline is removed (line continuation)
                         }
-                    } else {
-                        if let Some(next_ch) = chars.next() {
-                            match next_ch {
-                                ' ' => {
-                                    current_part.push(' ');
-                                }
-                                '\n' => {
-                                    // Backslash-newline is removed (line continuation)
-                                }
-                                _ => current_part.push(next_ch),
-                            }
-                        } else {
+                        Some(next_ch) => {
+                            flush_bare(&mut word, &mut bare);
+                            word.parts.push(WordPart::Quoted(next_ch.to_string()));
+                        }
+                        None => {
                             return Err(ShellError::IncompleteInput('\\'));
                         }
                     }
                 }
-                ' ' | '\t' => {
-                    if !in_quotes {
-                        if !current_part.is_empty() {
-                            let pushed = if !token_had_quotes {
-                                expand_tilde_word(&current_part)
-                            } else {
-                                current_part.clone()
-                            };
-                            parts.push(pushed);
-                            current_part.clear();
-                            token_had_quotes = false;
+                '$' => {
+                    self.pos += 1;
+                    match self.read_dollar()? {
+                        Some(part) => {
+                            flush_bare(&mut word, &mut bare);
+                            word.parts.push(part);
                         }
-                    } else {
-                        current_part.push(ch);
+                        None => bare.push('$'),
                     }
                 }
                 _ => {
-                    current_part.push(ch);
+                    self.pos += 1;
+                    bare.push(ch);
                 }
             }
         }
-    
-        if in_quotes {
-            return Err(ShellError::IncompleteInput(quote_char));
+
+        flush_bare(&mut word, &mut bare);
+
+        if word.parts.is_empty() && !had_quotes {
+            return Ok(None);
         }
-    
-        if !current_part.is_empty() {
-            let pushed = if !token_had_quotes {
-                expand_tilde_word(&current_part)
-            } else {
-                current_part.clone()
-            };
-            parts.push(pushed);
-        } else if in_quotes == false && !parts.is_empty() {
-            // Handle empty quoted strings as arguments
-            parts.push(String::new());
+        Ok(Some(word))
+    }
+
+    fn read_single_quoted(&mut self) -> Result<String, ShellError> {
+        let mut text = String::new();
+        loop {
+            match self.next() {
+                Some('\'') => return Ok(text),
+                Some('\\') => {
+                    let next_ch = self.next().ok_or(ShellError::IncompleteInput('\\'))?;
+                    push_escape(&mut text, next_ch, false);
+                }
+                Some(ch) => text.push(ch),
+                None => return Err(ShellError::IncompleteInput('\'')),
+            }
         }
-    
-        if parts.is_empty() {
-            return Ok(None);
+    }
+
+    fn read_double_quoted(&mut self, word: &mut Word) -> Result<(), ShellError> {
+        let mut text = String::new();
+        // Even `""` must leave a part behind so the word is not dropped.
+        word.parts.push(WordPart::Quoted(String::new()));
+        loop {
+            match self.next() {
+                Some('"') => {
+                    if !text.is_empty() {
+                        word.parts.push(WordPart::Quoted(text));
+                    }
+                    return Ok(());
+                }
+                Some('\\') => {
+                    let next_ch = self.next().ok_or(ShellError::IncompleteInput('\\'))?;
+                    push_escape(&mut text, next_ch, true);
+                }
+                Some('$') => {
+                    if let Some(part) = self.read_dollar()? {
+                        if !text.is_empty() {
+                            word.parts.push(WordPart::Quoted(std::mem::take(&mut text)));
+                        }
+                        word.parts.push(match part {
+                            WordPart::Parameter(name) => WordPart::QuotedParameter(name),
+                            part => part,
+                        });
+                    } else {
+                        text.push('$');
+                    }
+                }
+                Some(ch) => text.push(ch),
+                None => return Err(ShellError::IncompleteInput('"')),
+            }
+        }
+    }
+
+    /// Parses what follows a `$`. Returns `None` when the dollar sign is not
+    /// the start of an expansion and should be kept literally.
+    fn read_dollar(&mut self) -> Result<Option<WordPart>, ShellError> {
+        match self.peek() {
+            Some('(') if self.peek_at(1) == Some('(') => {
+                self.pos += 2;
+                Ok(Some(WordPart::Arithmetic(self.read_arithmetic()?)))
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut name = String::new();
+                loop {
+                    match self.next() {
+                        Some('}') => break,
+                        Some(ch) => name.push(ch),
+                        None => return Err(ShellError::IncompleteInput('}')),
+                    }
+                }
+                if !is_valid_parameter(&name) {
+                    return Err(ShellError::ParseError(format!("${{{}}}: bad substitution", name)));
+                }
+                Ok(Some(WordPart::Parameter(name)))
+            }
+            Some(ch) if ch == '?' || ch == '$' || ch.is_ascii_digit() => {
+                self.pos += 1;
+                Ok(Some(WordPart::Parameter(ch.to_string())))
+            }
+            Some(ch) if ch == '_' || ch.is_ascii_alphabetic() => {
+                let mut name = String::new();
+                while let Some(ch) = self.peek() {
+                    if ch == '_' || ch.is_ascii_alphanumeric() {
+                        name.push(ch);
+                        self.pos += 1;
+                    } else {
+                        break;
+                    }
+                }
+                Ok(Some(WordPart::Parameter(name)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Reads an arithmetic expression up to the `))` that closes it, with the
+    /// opening `((` already consumed.
+    fn read_arithmetic(&mut self) -> Result<String, ShellError> {
+        let mut expr = String::new();
+        let mut depth = 0usize;
+        loop {
+            match self.next() {
+                Some('(') => {
+                    depth += 1;
+                    expr.push('(');
+                }
+                Some(')') if depth == 0 => {
+                    if self.next() != Some(')') {
+                        return Err(ShellError::ParseError(
+                            "missing '))' in arithmetic expression".to_string()
+                        ));
+                    }
+                    return Ok(expr.trim().to_string());
+                }
+                Some(')') => {
+                    depth -= 1;
+                    expr.push(')');
+                }
+                Some(ch) => expr.push(ch),
+                None => {
+                    return Err(ShellError::ParseError(
+                        "missing '))' in arithmetic expression".to_string()
+                    ));
+                }
+            }
+        }
+    }
+}
+
+fn is_blank(ch: char) -> bool {
+    ch == ' ' || ch == '\t' || ch == '\n'
+}
+
+fn flush_bare(word: &mut Word, bare: &mut String) {
+    if !bare.is_empty() {
+        word.parts.push(WordPart::Bare(std::mem::take(bare)));
+    }
+}
+
+fn push_escape(text: &mut String, next_ch: char, in_double_quotes: bool) {
+    match next_ch {
+        'n' => text.push('\n'),
+        't' => text.push('\t'),
+        'r' => text.push('\r'),
+        '\\' | '"' | '\'' => text.push(next_ch),
+        '$' if in_double_quotes => text.push('$'),
+        '\n' => {
+            // Backslash-newline is removed (line continuation)
+        }
+        _ => {
+            text.push('\\');
+            text.push(next_ch);
         }
-    
-        let name = parts[0].clone();
-        let args = parts[1..].to_vec();
-    
-        Ok(Some(Command { name, args }))
     }
 }
-fn expand_tilde_word(word: &str) -> String {
-    if word == "~" {
-        return std::env::var("HOME").unwrap_or_else(|_| String::from("~"));
+
+fn is_valid_parameter(name: &str) -> bool {
+    if name == "?" || name == "$" || (!name.is_empty() && name.chars().all(|c| c.is_ascii_digit())) {
+        return true;
     }
-    if let Some(rest) = word.strip_prefix("~/") {
-        if let Ok(home) = std::env::var("HOME") {
-            return format!("{}/{}", home, rest);
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => {
+            chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
         }
+        _ => false,
     }
-    word.to_string()
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statement(input: &str) -> Statement {
+        let list = CommandParser::new().parse(input).unwrap().expect("a command");
+        match &list.items[..] {
+            [AndOr { first, rest }] if rest.is_empty() => first.clone(),
+            other => panic!("{:?}: expected one statement, got {:?}", input, other),
+        }
+    }
+
+    fn simple(input: &str) -> SimpleCommand {
+        match statement(input) {
+            Statement::Simple(command) => command,
+            other => panic!("{:?}: expected a simple command, got {:?}", input, other),
+        }
+    }
+
+    fn words(input: &str) -> Vec<Vec<WordPart>> {
+        simple(input).words.into_iter().map(|word| word.parts).collect()
+    }
+
+    fn bare(text: &str) -> WordPart {
+        WordPart::Bare(text.to_string())
+    }
+
+    fn quoted(text: &str) -> WordPart {
+        WordPart::Quoted(text.to_string())
+    }
+
+    fn word(parts: Vec<WordPart>) -> Word {
+        Word { parts }
+    }
+
+    #[test]
+    fn words_split_on_blanks() {
+        assert_eq!(words("  ls   -l\tdir  "), vec![vec![bare("ls")], vec![bare("-l")], vec![bare("dir")]]);
+        assert_eq!(CommandParser::new().parse("   ").unwrap(), None);
+    }
+
+    #[test]
+    fn parameters_and_arithmetic() {
+        assert_eq!(
+            words("echo $A ${B}c $? $((1 + 2))"),
+            vec![
+                vec![bare("echo")],
+                vec![WordPart::Parameter("A".to_string())],
+                vec![WordPart::Parameter("B".to_string()), bare("c")],
+                vec![WordPart::Parameter("?".to_string())],
+                vec![WordPart::Arithmetic("1 + 2".to_string())],
+            ]
+        );
+        assert_eq!(statement("(( x = 1 + 2 ))"), Statement::Arithmetic("x = 1 + 2".to_string()));
+    }
+
+    #[test]
+    fn quotes_and_escapes() {
+        assert_eq!(words("echo 'a $B \"c'")[1], vec![quoted("a $B \"c")]);
+        assert_eq!(
+            words("echo \"a $B\"")[1],
+            vec![quoted(""), quoted("a "), WordPart::QuotedParameter("B".to_string())]
+        );
+        assert_eq!(words("echo \"a\\\"b\\$c\"")[1], vec![quoted(""), quoted("a\"b$c")]);
+        assert_eq!(words("echo a\\ b")[1], vec![bare("a"), quoted(" "), bare("b")]);
+        assert_eq!(words("echo x'y'\"z\"")[1], vec![bare("x"), quoted("y"), quoted(""), quoted("z")]);
+        assert_eq!(words("echo ''").len(), 2);
+    }
+
+    #[test]
+    fn unterminated_quotes_ask_for_more_input() {
+        assert!(matches!(CommandParser::new().parse("echo 'abc"), Err(ShellError::IncompleteInput('\''))));
+        assert!(matches!(CommandParser::new().parse("echo \"abc"), Err(ShellError::IncompleteInput('"'))));
+    }
+
+    #[test]
+    fn redirects() {
+        let command = simple("cat < in > out 2>&1 >> log >| f 3<&0 <<< 'w' arg");
+        assert_eq!(command.words, vec![word(vec![bare("cat")]), word(vec![bare("arg")])]);
+        assert_eq!(
+            command.redirects,
+            vec![
+                Redirect { fd: 0, kind: RedirectKind::Input(word(vec![bare("in")])) },
+                Redirect { fd: 1, kind: RedirectKind::Output(word(vec![bare("out")])) },
+                Redirect { fd: 2, kind: RedirectKind::Duplicate(1) },
+                Redirect { fd: 1, kind: RedirectKind::Append(word(vec![bare("log")])) },
+                Redirect { fd: 1, kind: RedirectKind::Clobber(word(vec![bare("f")])) },
+                Redirect { fd: 3, kind: RedirectKind::Duplicate(0) },
+                Redirect { fd: 0, kind: RedirectKind::HereString(word(vec![quoted("w")])) },
+            ]
+        );
+    }
+
+    #[test]
+    fn redirect_without_target_is_a_syntax_error() {
+        assert!(matches!(CommandParser::new().parse("echo a >"), Err(ShellError::ParseError(_))));
+    }
+
+    #[test]
+    fn here_documents() {
+        let expanded = simple("cat <<EOF\nhi $X\nEOF");
+        assert_eq!(
+            expanded.redirects,
+            vec![Redirect {
+                fd: 0,
+                kind: RedirectKind::HereDoc(word(vec![
+                    quoted("hi "),
+                    WordPart::Parameter("X".to_string()),
+                    quoted("\n"),
+                ])),
+            }]
+        );
+
+        let literal = simple("cat <<'EOF'\nhi $X\nEOF");
+        assert_eq!(
+            literal.redirects,
+            vec![Redirect { fd: 0, kind: RedirectKind::HereDoc(word(vec![quoted("hi $X\n")])) }]
+        );
+    }
+}
@@ -0,0 +1,177 @@
+use crate::encoding;
+use crate::error::ShellError;
+use std::fs::{ self, File, OpenOptions };
+use std::io::{ self, Seek, SeekFrom, Write };
+use std::os::unix::io::IntoRawFd;
+use std::path::{ Path, PathBuf };
+
+// Built-in commands run inside the shell process and write through the
+// ordinary stdin/stdout/stderr descriptors, so a redirection is applied by
+// pointing those descriptors somewhere else for the duration of the command
+// and putting the originals back afterwards.
+
+/// A redirection with its target already expanded.
+#[derive(Debug)]
+pub enum ResolvedRedirect {
+    Input { fd: i32, path: PathBuf },
+    /// With `noclobber` an existing regular file is left alone.
+    Output { fd: i32, path: PathBuf, noclobber: bool },
+    Append { fd: i32, path: PathBuf },
+    Duplicate { fd: i32, target: i32 },
+    /// Here-documents and here-strings: the text becomes the descriptor's
+    /// contents.
+    Text { fd: i32, text: String },
+}
+
+/// Restores the redirected descriptors when dropped.
+pub struct RedirectGuard {
+    saved: Vec<(i32, i32)>,
+}
+
+// Saved copies are moved above the range scripts normally use.
+const SAVED_FD_BASE: i32 = 10;
+
+pub fn apply(redirects: &[ResolvedRedirect]) -> Result<RedirectGuard, ShellError> {
+    let mut guard = RedirectGuard { saved: Vec::new() };
+    if redirects.is_empty() {
+        return Ok(guard);
+    }
+
+    flush_std_streams();
+    for redirect in redirects {
+        // On error the guard is dropped here, undoing what was applied so far.
+        guard.apply_one(redirect)?;
+    }
+    Ok(guard)
+}
+
+impl RedirectGuard {
+    fn apply_one(&mut self, redirect: &ResolvedRedirect) -> Result<(), ShellError> {
+        match redirect {
+            ResolvedRedirect::Input { fd, path } => {
+                let file = File::open(path).map_err(|e| open_error(path, e))?;
+                self.replace(*fd, file.into_raw_fd())
+            }
+            ResolvedRedirect::Output { fd, path, noclobber } => {
+                let mut options = OpenOptions::new();
+                options.write(true);
+                match fs::metadata(path) {
+                    Ok(meta) if *noclobber && meta.is_file() => {
+                        return Err(ShellError::FileSystemError(
+                            format!("{}: cannot overwrite existing file", path.display())
+                        ));
+                    }
+                    // Devices such as /dev/null stay writable under noclobber.
+                    Ok(_) if *noclobber => {}
+                    _ if *noclobber => {
+                        options.create_new(true);
+                    }
+                    _ => {
+                        options.create(true).truncate(true);
+                    }
+                }
+                let file = options.open(path).map_err(|e| open_error(path, e))?;
+                self.replace(*fd, file.into_raw_fd())
+            }
+            ResolvedRedirect::Append { fd, path } => {
+                let file = OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(path)
+                    .map_err(|e| open_error(path, e))?;
+                self.replace(*fd, file.into_raw_fd())
+            }
+            ResolvedRedirect::Duplicate { fd, target } => {
+                self.save(*fd)?;
+                if unsafe { libc::dup2(*target, *fd) } < 0 {
+                    return Err(ShellError::ExecutionError(
+                        format!("{}: Bad file descriptor", target)
+                    ));
+                }
+                Ok(())
+            }
+            ResolvedRedirect::Text { fd, text } => {
+                let file = text_file(text)?;
+                self.replace(*fd, file.into_raw_fd())
+            }
+        }
+    }
+
+    /// Moves `new_fd` onto `fd`, remembering what `fd` was first.
+    fn replace(&mut self, fd: i32, new_fd: i32) -> Result<(), ShellError> {
+        self.save(fd)?;
+        let result = unsafe { libc::dup2(new_fd, fd) };
+        let err = io::Error::last_os_error();
+        unsafe {
+            libc::close(new_fd);
+        }
+        if result < 0 {
+            return Err(ShellError::IoError(err));
+        }
+        Ok(())
+    }
+
+    fn save(&mut self, fd: i32) -> Result<(), ShellError> {
+        if self.saved.iter().any(|(original, _)| *original == fd) {
+            return Ok(());
+        }
+        // -1 records that the descriptor was closed before the redirection.
+        let copy = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, SAVED_FD_BASE) };
+        self.saved.push((fd, copy));
+        Ok(())
+    }
+}
+
+impl Drop for RedirectGuard {
+    fn drop(&mut self) {
+        if self.saved.is_empty() {
+            return;
+        }
+        flush_std_streams();
+        for (fd, copy) in self.saved.drain(..).rev() {
+            unsafe {
+                if copy >= 0 {
+                    libc::dup2(copy, fd);
+                    libc::close(copy);
+                } else {
+                    libc::close(fd);
+                }
+            }
+        }
+    }
+}
+
+fn flush_std_streams() {
+    let _ = io::stdout().flush();
+    let _ = io::stderr().flush();
+}
+
+/// Stores `text` in an anonymous temporary file positioned at its start.
+/// Unlike a pipe this cannot block however large the text is.
+fn text_file(text: &str) -> Result<File, ShellError> {
+    let dir = std::env::temp_dir();
+    let mut attempt = 0;
+    let (mut file, path) = loop {
+        let path = dir.join(format!("0-shell-{}-{}", std::process::id(), attempt));
+        match OpenOptions::new().read(true).write(true).create_new(true).open(&path) {
+            Ok(file) => break (file, path),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => attempt += 1,
+            Err(e) => return Err(ShellError::IoError(e)),
+        }
+    };
+    let _ = fs::remove_file(&path);
+
+    file.write_all(&encoding::encode_bytes(text))?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+fn open_error(path: &Path, e: io::Error) -> ShellError {
+    let reason = match e.kind() {
+        io::ErrorKind::NotFound => "No such file or directory".to_string(),
+        io::ErrorKind::PermissionDenied => "Permission denied".to_string(),
+        io::ErrorKind::IsADirectory => "Is a directory".to_string(),
+        _ => e.to_string(),
+    };
+    ShellError::FileSystemError(format!("{}: {}", path.display(), reason))
+}
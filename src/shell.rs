@@ -1,12 +1,119 @@
-use std::io::{ self, Write };
-use crate::commands::CommandRegistry;
-use crate::parser::CommandParser;
+use std::collections::{ BTreeMap, HashMap };
+use std::ffi::OsString;
+use std::io::{ self, BufRead, Write };
+use std::path::PathBuf;
+use crate::arithmetic;
+use crate::commands::{ self, CommandRegistry, CondArg };
+use crate::parser::{
+    Command, CommandParser, CondToken, Connector, List, Redirect, RedirectKind, Statement, Word, WordPart,
+};
+use crate::encoding;
+use crate::redirect::{ self, ResolvedRedirect };
 use crate::error::ShellError;
+use crate::trap::{ self, Disposition, TrapCondition };
+
+/// Options changed with `set`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShellOptions {
+    /// `-e`: exit as soon as a command fails.
+    pub errexit: bool,
+    /// `-u`: expanding an unset variable is an error.
+    pub nounset: bool,
+    /// `-x`: print each command to stderr before running it.
+    pub xtrace: bool,
+    /// `-o pipefail`: accepted for compatibility. Every command is its own
+    /// pipeline here, so its status is already the pipeline's.
+    pub pipefail: bool,
+    /// `-C`: `>` refuses to overwrite an existing file.
+    pub noclobber: bool,
+}
+
+impl ShellOptions {
+    /// Long names as used by `set -o`, with their single-letter flags.
+    const NAMES: [(&'static str, Option<char>); 5] = [
+        ("errexit", Some('e')),
+        ("noclobber", Some('C')),
+        ("nounset", Some('u')),
+        ("pipefail", None),
+        ("xtrace", Some('x')),
+    ];
+
+    fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "errexit" => Some(&mut self.errexit),
+            "noclobber" => Some(&mut self.noclobber),
+            "nounset" => Some(&mut self.nounset),
+            "pipefail" => Some(&mut self.pipefail),
+            "xtrace" => Some(&mut self.xtrace),
+            _ => None,
+        }
+    }
+
+    fn get(&self, name: &str) -> bool {
+        match name {
+            "errexit" => self.errexit,
+            "noclobber" => self.noclobber,
+            "nounset" => self.nounset,
+            "pipefail" => self.pipefail,
+            "xtrace" => self.xtrace,
+            _ => false,
+        }
+    }
+
+    fn name_for_flag(flag: char) -> Option<&'static str> {
+        Self::NAMES.iter().find(|(_, f)| *f == Some(flag)).map(|(name, _)| *name)
+    }
+}
+
+/// A builtin that changes the shell itself, such as its options, and so is
+/// handed the `Shell` instead of going through the command registry.
+pub trait ShellBuiltin: Sync {
+    fn execute(&self, shell: &mut Shell, args: &[OsString]) -> Result<(), ShellError>;
+    fn help(&self) -> &str;
+}
+
+struct SetBuiltin;
+
+impl ShellBuiltin for SetBuiltin {
+    fn execute(&self, shell: &mut Shell, args: &[OsString]) -> Result<(), ShellError> {
+        shell.run_set(&commands::text_args(args))
+    }
+
+    fn help(&self) -> &str {
+        "set [-euxC] [-o option] - Set or unset shell options (errexit, nounset, xtrace, noclobber; \
+         pipefail is accepted but does nothing, as there are no pipelines)"
+    }
+}
+
+struct TrapBuiltin;
+
+impl ShellBuiltin for TrapBuiltin {
+    fn execute(&self, shell: &mut Shell, args: &[OsString]) -> Result<(), ShellError> {
+        shell.run_trap(&commands::text_args(args))
+    }
+
+    fn help(&self) -> &str {
+        "trap [-lp] [action] [signal...] - Run an action on a signal, EXIT or ERR ('' ignores, - resets)"
+    }
+}
+
+const SHELL_BUILTINS: &[(&str, &dyn ShellBuiltin)] = &[("set", &SetBuiltin), ("trap", &TrapBuiltin)];
+
+pub fn shell_builtin(name: &str) -> Option<&'static dyn ShellBuiltin> {
+    SHELL_BUILTINS.iter().find(|(builtin, _)| *builtin == name).map(|(_, builtin)| *builtin)
+}
 
 pub struct Shell {
     command_registry: CommandRegistry,
     parser: CommandParser,
     last_dir: std::path::PathBuf,
+    last_status: i32,
+    options: ShellOptions,
+    /// Actions set with `trap`. An empty action means the signal is ignored.
+    traps: BTreeMap<TrapCondition, String>,
+    /// Set while a trap action runs, so a failure inside an ERR trap or a
+    /// signal arriving mid-trap does not start another one.
+    in_trap: bool,
 }
 
 impl Shell {
@@ -15,14 +122,28 @@ impl Shell {
             command_registry: CommandRegistry::new(),
             parser: CommandParser::new(),
             last_dir: std::path::PathBuf::new(),
+            last_status: 0,
+            options: ShellOptions::default(),
+            traps: BTreeMap::new(),
+            in_trap: false,
         }
     }
 
-    pub fn run(&mut self) -> Result<(), ShellError> {
+    /// Runs the read-execute loop and returns the status to exit with.
+    pub fn run(&mut self) -> Result<i32, ShellError> {
+        let status = self.read_eval_loop()?;
+        Ok(self.run_exit_trap(status))
+    }
+
+    fn read_eval_loop(&mut self) -> Result<i32, ShellError> {
         loop {
             self.display_prompt()?;
 
             let input = self.read_complete_input()?;
+            // Signals that came in while waiting for input.
+            if let Err(ShellError::Exit(code)) = self.run_pending_traps() {
+                return Ok(code.unwrap_or(self.last_status));
+            }
 
             match input {
                 None => {
@@ -33,13 +154,13 @@ impl Shell {
                     continue;
                 }
                 Some(input) => {
-                    if let Err(e) = self.execute_command(&input) {
-                        eprintln!("Error: {}", e);
+                    if let Err(ShellError::Exit(code)) = self.execute_command(&input) {
+                        return Ok(code.unwrap_or(self.last_status));
                     }
                 }
             }
         }
-        Ok(())
+        Ok(self.last_status)
     }
 
     fn display_prompt(&mut self) -> Result<(), ShellError> {
@@ -72,21 +193,53 @@ impl Shell {
         io
             ::stdout()
             .flush()
-            .map_err(|e| ShellError::IoError(e))?;
+            .map_err(ShellError::IoError)?;
         Ok(())
     }
 
+    /// Reads one line as bytes; see `encoding` for how bytes that are not
+    /// UTF-8 are kept. A trapped signal cuts the wait short and gives an
+    /// empty line, so that the trap runs right away.
     fn read_input(&self) -> Result<Option<String>, ShellError> {
-        let mut input = String::new();
-        let bytes_read = io
-            ::stdin()
-            .read_line(&mut input)
-            .map_err(|e| ShellError::IoError(e))?;
+        let stdin = io::stdin();
+        let mut reader = stdin.lock();
+        let mut input = Vec::new();
+        loop {
+            // read_until would retry after the signal, so this is done by
+            // hand.
+            let available = match reader.fill_buf() {
+                Ok(available) => available,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                    if trap::interrupted() {
+                        // The terminal has dropped what was typed, so the
+                        // prompt goes on a line of its own.
+                        if unsafe { libc::isatty(libc::STDIN_FILENO) } == 1 {
+                            println!();
+                        }
+                        return Ok(Some(String::new()));
+                    }
+                    continue;
+                }
+                Err(e) => return Err(ShellError::IoError(e)),
+            };
+            if available.is_empty() {
+                break;
+            }
+            let (taken, done) = match available.iter().position(|&byte| byte == b'\n') {
+                Some(end) => (end + 1, true),
+                None => (available.len(), false),
+            };
+            input.extend_from_slice(&available[..taken]);
+            reader.consume(taken);
+            if done {
+                break;
+            }
+        }
 
-        if bytes_read == 0 {
+        if input.is_empty() {
             return Ok(None);
         }
-        Ok(Some(input))
+        Ok(Some(encoding::decode(&input)))
     }
 
     fn read_complete_input(&self) -> Result<Option<String>, ShellError> {
@@ -101,22 +254,21 @@ impl Shell {
                 }
             };
 
-            if line.is_empty() && complete_input.is_empty() {
+            // Interrupted: whatever was typed so far is dropped.
+            if line.is_empty() {
                 return Ok(Some(String::new()));
             }
 
             complete_input.push_str(&line);
             
             match self.parser.parse(&complete_input) {
-                Ok(_) => {
-                    break;
-                }
-                Err(ShellError::IncompleteInput(_)) => {
+                Err(ShellError::IncompleteInput(_) | ShellError::IncompleteHereDoc(_)) => {
                     self.display_continuation_prompt()?;
                     continue;
                 }
-                Err(e) => {
-                    return Err(e);
+                // Syntax errors are reported when the input is executed.
+                _ => {
+                    break;
                 }
             }
         }
@@ -125,12 +277,565 @@ impl Shell {
     }
 
     fn execute_command(&mut self, input: &str) -> Result<(), ShellError> {
-        let command = self.parser.parse(input)?;
+        match self.parser.parse(input) {
+            Ok(Some(list)) => self.run_list(&list),
+            Ok(None) => Ok(()),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                self.last_status = 2;
+                Ok(())
+            }
+        }
+    }
+
+    /// Runs each and-or chain in turn. Failures are reported as they happen
+    /// and only leave their status in `$?`; the one error passed up is a
+    /// request to exit.
+    fn run_list(&mut self, list: &List) -> Result<(), ShellError> {
+        for item in &list.items {
+            self.run_statement(&item.first)?;
+            let mut last_ran = item.rest.is_empty();
+            let mut last = &item.first;
+            for (i, (connector, statement)) in item.rest.iter().enumerate() {
+                let proceed = match connector {
+                    Connector::And => self.last_status == 0,
+                    Connector::Or => self.last_status != 0,
+                };
+                if proceed {
+                    self.run_statement(statement)?;
+                    last_ran = i + 1 == item.rest.len();
+                    last = statement;
+                }
+            }
+            // Commands to the left of `&&` and `||` are being tested, so
+            // only a failure of the last one in the chain trips `set -e`
+            // or the ERR trap.
+            if !last_ran || self.last_status == 0 {
+                continue;
+            }
+            // A failing `{ }` group already ran the trap for the command
+            // inside it that failed.
+            if !matches!(last, Statement::Group(..)) {
+                self.run_err_trap()?;
+            }
+            if self.options.errexit {
+                return Err(ShellError::Exit(Some(self.last_status)));
+            }
+        }
+        Ok(())
+    }
+
+    fn run_statement(&mut self, statement: &Statement) -> Result<(), ShellError> {
+        self.last_status = match self.execute_statement(statement) {
+            Ok(()) => 0,
+            Err(e @ ShellError::Exit(_)) => return Err(e),
+            Err(e) => {
+                if !matches!(e, ShellError::ExitStatus(_)) {
+                    eprintln!("Error: {}", e);
+                }
+                e.exit_status()
+            }
+        };
+        self.run_pending_traps()
+    }
+
+    fn execute_statement(&mut self, statement: &Statement) -> Result<(), ShellError> {
+        match statement {
+            Statement::Simple(command) => {
+                let split = command.words.iter().take_while(|w| w.as_assignment().is_some()).count();
+                let (assignment_words, words) = command.words.split_at(split);
+                let mut assignments = Vec::with_capacity(split);
+                for word in assignment_words {
+                    if let Some((name, value)) = word.as_assignment() {
+                        assignments.push((name, self.expand_word(&value)?));
+                    }
+                }
+                let cmd = self.expand_command(words)?;
+                let redirects = self.resolve_redirects(&command.redirects)?;
+
+                let traced = assignments.iter().map(|(name, value)| format!("{}={}", name, quote_for_trace(value)));
+                let traced_cmd = cmd.iter().flat_map(|c| {
+                    std::iter::once(c.name.clone()).chain(c.args.iter().map(|arg| encoding::decode_os(arg)))
+                }).map(|w| quote_for_trace(&w));
+                self.trace(traced.chain(traced_cmd));
+
+                let _guard = redirect::apply(&redirects)?;
+                let Some(cmd) = cmd else {
+                    for (name, value) in &assignments {
+                        unsafe {
+                            std::env::set_var(name, encoding::encode(value));
+                        }
+                    }
+                    return Ok(());
+                };
+
+                // `NAME=value cmd` sets the variables for that command only.
+                let previous: Vec<(String, Option<OsString>)> = assignments.iter()
+                    .map(|(name, _)| (name.clone(), std::env::var_os(name)))
+                    .collect();
+                for (name, value) in &assignments {
+                    unsafe {
+                        std::env::set_var(name, encoding::encode(value));
+                    }
+                }
+                let result = match shell_builtin(&cmd.name) {
+                    Some(builtin) => builtin.execute(self, &cmd.args),
+                    None => self.command_registry.execute(&cmd),
+                };
+                for (name, value) in previous.into_iter().rev() {
+                    unsafe {
+                        match value {
+                            Some(value) => std::env::set_var(&name, value),
+                            None => std::env::remove_var(&name),
+                        }
+                    }
+                }
+                result
+            }
+            Statement::Arithmetic(expr) => {
+                self.trace(["((".to_string(), expr.clone(), "))".to_string()].into_iter());
+                status_result(if arithmetic::evaluate(expr)? != 0 { 0 } else { 1 })
+            }
+            Statement::Conditional(tokens) => {
+                let mut args = Vec::with_capacity(tokens.len());
+                for token in tokens {
+                    args.push(match token {
+                        CondToken::Word(word) => CondArg::Word(self.expand_segments(word)?),
+                        CondToken::Operator(op) => CondArg::Operator(op),
+                    });
+                }
+                let shown = args.iter().map(|arg| match arg {
+                    CondArg::Word(segments) => quote_for_trace(&segments.iter().map(|(t, _)| t.as_str()).collect::<String>()),
+                    CondArg::Operator(op) => op.to_string(),
+                });
+                self.trace(std::iter::once("[[".to_string()).chain(shown).chain(std::iter::once("]]".to_string())));
+                status_result(if commands::evaluate_conditional(&args)? { 0 } else { 1 })
+            }
+            Statement::Group(body, redirects) => {
+                let redirects = self.resolve_redirects(redirects)?;
+                let _guard = redirect::apply(&redirects)?;
+                self.run_list(body)?;
+                status_result(self.last_status)
+            }
+            Statement::Subshell(body, redirects) => {
+                let redirects = self.resolve_redirects(redirects)?;
+                let _guard = redirect::apply(&redirects)?;
+                let snapshot = self.snapshot();
+                // A subshell starts with the parent's traps reset, except
+                // for signals the parent ignores.
+                self.traps.retain(|_, action| action.is_empty());
+                self.apply_signal_dispositions();
+                let status = match self.run_list(body) {
+                    // `exit` only leaves the subshell.
+                    Err(ShellError::Exit(code)) => Ok(code.unwrap_or(self.last_status)),
+                    Err(e) => Err(e),
+                    Ok(()) => Ok(self.last_status),
+                }.map(|status| self.run_exit_trap(status));
+                self.restore(snapshot);
+                self.apply_signal_dispositions();
+                status_result(status?)
+            }
+        }
+    }
 
-        if let Some(cmd) = command {
-            self.command_registry.execute(&cmd)?;
+    /// Built-ins run in this process, so a subshell is emulated by saving
+    /// everything its commands can change and putting it back afterwards.
+    fn snapshot(&self) -> StateSnapshot {
+        StateSnapshot {
+            cwd: std::env::current_dir().ok(),
+            vars: std::env::vars_os().collect(),
+            options: self.options,
+            traps: self.traps.clone(),
         }
+    }
+
+    fn restore(&mut self, snapshot: StateSnapshot) {
+        self.options = snapshot.options;
+        self.traps = snapshot.traps;
+        // Variables set inside, OLDPWD from `cd` among them, are dropped.
+        let saved: HashMap<OsString, OsString> = snapshot.vars.into_iter().collect();
+        for (name, _) in std::env::vars_os() {
+            if !saved.contains_key(&name) {
+                unsafe {
+                    std::env::remove_var(&name);
+                }
+            }
+        }
+        for (name, value) in &saved {
+            if std::env::var_os(name).as_ref() != Some(value) {
+                unsafe {
+                    std::env::set_var(name, value);
+                }
+            }
+        }
+        if let Some(cwd) = snapshot.cwd
+            && let Err(e) = std::env::set_current_dir(&cwd)
+        {
+            eprintln!("Error: cannot return to '{}': {}", cwd.display(), e);
+        }
+    }
+
+    fn expand_command(&self, words: &[Word]) -> Result<Option<Command>, ShellError> {
+        let mut parts = Vec::with_capacity(words.len());
+        for word in words {
+            parts.push(self.expand_word(word)?);
+        }
+
+        if parts.is_empty() {
+            return Ok(None);
+        }
+        let name = parts.remove(0);
+        let args = parts.iter().map(|part| encoding::encode(part)).collect();
+        Ok(Some(Command { name, args }))
+    }
+
+    /// Prints a command for `set -x`, prefixed with `$PS4`.
+    fn trace(&self, words: impl Iterator<Item = String>) {
+        if !self.options.xtrace {
+            return;
+        }
+        let prefix = std::env::var("PS4").unwrap_or_else(|_| "+ ".to_string());
+        let line: Vec<String> = words.collect();
+        eprintln!("{}{}", prefix, line.join(" "));
+    }
 
+    fn run_set(&mut self, args: &[String]) -> Result<(), ShellError> {
+        if args.is_empty() {
+            let mut vars: Vec<(String, String)> = std::env::vars().collect();
+            vars.sort();
+            for (name, value) in vars {
+                println!("{}={}", name, quote_for_trace(&value));
+            }
+            return Ok(());
+        }
+
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if arg == "--" || arg == "-" {
+                break;
+            }
+            let enable = match arg.chars().next() {
+                Some('-') => true,
+                Some('+') => false,
+                _ => {
+                    return Err(ShellError::ExecutionError(
+                        "set: positional parameters are not supported".to_string()
+                    ));
+                }
+            };
+
+            if &arg[1..] == "o" {
+                match iter.next() {
+                    Some(name) => {
+                        let option = self.options.get_mut(name).ok_or_else(|| {
+                            ShellError::InvalidOption(format!("set: {}: invalid option name", name))
+                        })?;
+                        *option = enable;
+                    }
+                    None => self.print_options(enable),
+                }
+                continue;
+            }
+
+            for flag in arg[1..].chars() {
+                let name = ShellOptions::name_for_flag(flag).ok_or_else(|| {
+                    ShellError::InvalidOption(format!("set: {}{}: invalid option", &arg[..1], flag))
+                })?;
+                if let Some(option) = self.options.get_mut(name) {
+                    *option = enable;
+                }
+            }
+        }
+
+        if iter.next().is_some() {
+            return Err(ShellError::ExecutionError(
+                "set: positional parameters are not supported".to_string()
+            ));
+        }
+        Ok(())
+    }
+
+    fn run_trap(&mut self, args: &[String]) -> Result<(), ShellError> {
+        let args = match args.first() {
+            Some(first) if first == "--" => &args[1..],
+            _ => args,
+        };
+        match args.first().map(String::as_str) {
+            None => return self.print_traps(&[]),
+            Some("-p") => return self.print_traps(&args[1..]),
+            Some("-l") => {
+                for (name, number) in trap::SIGNALS {
+                    println!("{:>2}) SIG{}", number, name);
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        // `trap SIG...` with a lone signal, or `trap - SIG...`, resets.
+        let (action, specs) = if args.len() == 1 || args[0] == "-" {
+            let specs = if args[0] == "-" { &args[1..] } else { args };
+            (None, specs)
+        } else {
+            (Some(&args[0]), &args[1..])
+        };
+
+        let mut invalid = Vec::new();
+        for spec in specs {
+            match TrapCondition::parse(spec) {
+                Some(condition) => match action {
+                    Some(action) => {
+                        self.traps.insert(condition, action.clone());
+                    }
+                    None => {
+                        self.traps.remove(&condition);
+                    }
+                },
+                None => invalid.push(spec.as_str()),
+            }
+        }
+        self.apply_signal_dispositions();
+
+        if !invalid.is_empty() {
+            return Err(ShellError::ExecutionError(format!(
+                "trap: {}: invalid signal specification", invalid.join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    /// Prints traps as the `trap` commands that would set them again.
+    fn print_traps(&self, specs: &[String]) -> Result<(), ShellError> {
+        let mut conditions = Vec::with_capacity(specs.len());
+        for spec in specs {
+            conditions.push(TrapCondition::parse(spec).ok_or_else(|| {
+                ShellError::ExecutionError(format!("trap: {}: invalid signal specification", spec))
+            })?);
+        }
+        for (condition, action) in &self.traps {
+            if conditions.is_empty() || conditions.contains(condition) {
+                println!("trap -- '{}' {}", action.replace('\'', "'\\''"), condition.name());
+            }
+        }
+        Ok(())
+    }
+
+    /// Installs handlers to match the trap table. A fatal signal without its
+    /// own trap is still caught while an EXIT trap is set, so the EXIT trap
+    /// gets to run before the shell dies.
+    fn apply_signal_dispositions(&self) {
+        let exit_trap = self.traps.contains_key(&TrapCondition::Exit);
+        for (_, signal) in trap::SIGNALS {
+            let disposition = match self.traps.get(&TrapCondition::Signal(*signal)) {
+                Some(action) if action.is_empty() => Disposition::Ignore,
+                Some(_) => Disposition::Catch,
+                None if exit_trap && trap::FATAL_SIGNALS.contains(signal) => Disposition::Catch,
+                None => Disposition::Default,
+            };
+            trap::set_disposition(*signal, disposition);
+        }
+    }
+
+    /// Runs the actions for signals that arrived during the last command.
+    fn run_pending_traps(&mut self) -> Result<(), ShellError> {
+        if self.in_trap {
+            return Ok(());
+        }
+        for signal in trap::take_pending() {
+            match self.traps.get(&TrapCondition::Signal(signal)).cloned() {
+                Some(action) => self.run_trap_action(&action)?,
+                None => {
+                    // Only caught to give the EXIT trap its turn.
+                    self.run_exit_trap(128 + signal);
+                    trap::terminate_with(signal);
+                }
+            }
+        }
         Ok(())
     }
+
+    fn run_err_trap(&mut self) -> Result<(), ShellError> {
+        if self.in_trap {
+            return Ok(());
+        }
+        match self.traps.get(&TrapCondition::Err).cloned() {
+            Some(action) => self.run_trap_action(&action),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs the EXIT trap once, returning the status to exit with. An
+    /// `exit n` inside the trap replaces it.
+    fn run_exit_trap(&mut self, status: i32) -> i32 {
+        let Some(action) = self.traps.remove(&TrapCondition::Exit) else {
+            return status;
+        };
+        self.last_status = status;
+        match self.run_trap_action(&action) {
+            Err(ShellError::Exit(Some(code))) => code,
+            _ => status,
+        }
+    }
+
+    /// Runs a trap action without disturbing `$?`.
+    fn run_trap_action(&mut self, action: &str) -> Result<(), ShellError> {
+        let list = match self.parser.parse(action) {
+            Ok(Some(list)) => list,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                eprintln!("Error: trap: {}", e);
+                return Ok(());
+            }
+        };
+        let status = self.last_status;
+        let was_in_trap = std::mem::replace(&mut self.in_trap, true);
+        let result = self.run_list(&list);
+        self.in_trap = was_in_trap;
+        self.last_status = status;
+        result
+    }
+
+    /// `set -o` lists the options in a readable table, `set +o` as commands
+    /// that would restore them.
+    fn print_options(&self, readable: bool) {
+        for (name, _) in ShellOptions::NAMES {
+            let on = self.options.get(name);
+            if readable {
+                println!("{:<15}{}", name, if on { "on" } else { "off" });
+            } else {
+                println!("set {}o {}", if on { '-' } else { '+' }, name);
+            }
+        }
+    }
+
+    fn resolve_redirects(&self, redirects: &[Redirect]) -> Result<Vec<ResolvedRedirect>, ShellError> {
+        let mut resolved = Vec::with_capacity(redirects.len());
+        for Redirect { fd, kind } in redirects {
+            let fd = *fd;
+            resolved.push(match kind {
+                RedirectKind::Input(word) => ResolvedRedirect::Input { fd, path: self.expand_path(word)? },
+                RedirectKind::Output(word) => ResolvedRedirect::Output {
+                    fd,
+                    path: self.expand_path(word)?,
+                    noclobber: self.options.noclobber,
+                },
+                RedirectKind::Clobber(word) => ResolvedRedirect::Output {
+                    fd,
+                    path: self.expand_path(word)?,
+                    noclobber: false,
+                },
+                RedirectKind::Append(word) => ResolvedRedirect::Append { fd, path: self.expand_path(word)? },
+                RedirectKind::Duplicate(target) => ResolvedRedirect::Duplicate { fd, target: *target },
+                RedirectKind::HereDoc(body) => ResolvedRedirect::Text { fd, text: self.expand_word(body)? },
+                RedirectKind::HereString(word) => {
+                    let mut text = self.expand_word(word)?;
+                    text.push('\n');
+                    ResolvedRedirect::Text { fd, text }
+                }
+            });
+        }
+        Ok(resolved)
+    }
+
+    fn expand_path(&self, word: &Word) -> Result<PathBuf, ShellError> {
+        Ok(PathBuf::from(encoding::encode(&self.expand_word(word)?)))
+    }
+
+    fn expand_word(&self, word: &Word) -> Result<String, ShellError> {
+        Ok(self.expand_segments(word)?.into_iter().map(|(text, _)| text).collect())
+    }
+
+    /// Expands each part of a word, remembering whether it was quoted.
+    fn expand_segments(&self, word: &Word) -> Result<Vec<(String, bool)>, ShellError> {
+        let mut segments = Vec::with_capacity(word.parts.len());
+        for (i, part) in word.parts.iter().enumerate() {
+            segments.push(match part {
+                WordPart::Bare(text) if i == 0 => {
+                    let tilde_prefix = i + 1 == word.parts.len() || text.contains('/');
+                    if tilde_prefix {
+                        let expanded = expand_tilde_word(text);
+                        let changed = expanded != *text;
+                        (expanded, changed)
+                    } else {
+                        (text.clone(), false)
+                    }
+                }
+                WordPart::Bare(text) => (text.clone(), false),
+                WordPart::Quoted(text) => (text.clone(), true),
+                WordPart::Parameter(name) => (self.lookup_parameter(name)?, false),
+                WordPart::QuotedParameter(name) => (self.lookup_parameter(name)?, true),
+                WordPart::Arithmetic(expr) => (arithmetic::evaluate(expr)?.to_string(), true),
+            });
+        }
+        Ok(segments)
+    }
+
+    fn lookup_parameter(&self, name: &str) -> Result<String, ShellError> {
+        Ok(match name {
+            "?" => self.last_status.to_string(),
+            "$" => std::process::id().to_string(),
+            "0" => "0-shell".to_string(),
+            _ => match std::env::var_os(name) {
+                Some(value) => encoding::decode_os(&value),
+                None if self.options.nounset => {
+                    return Err(ShellError::ExecutionError(format!("{}: unbound variable", name)));
+                }
+                None => String::new(),
+            },
+        })
+    }
+}
+
+struct StateSnapshot {
+    cwd: Option<PathBuf>,
+    vars: Vec<(OsString, OsString)>,
+    options: ShellOptions,
+    traps: BTreeMap<TrapCondition, String>,
+}
+
+/// Single-quotes a word for display if it would not read back as one word.
+/// Bytes that are not UTF-8 are shown as `$'\xNN'`.
+fn quote_for_trace(word: &str) -> String {
+    let plain = !word.is_empty() && word.chars().all(|c| {
+        c.is_ascii_alphanumeric() || "-_./=:,+@%^".contains(c)
+    });
+    if plain {
+        return word.to_string();
+    }
+    if word.chars().any(|c| encoding::escaped_byte(c).is_some()) {
+        let mut quoted = String::from("$'");
+        for c in word.chars() {
+            match (encoding::escaped_byte(c), c) {
+                (Some(byte), _) => quoted.push_str(&format!("\\x{:02x}", byte)),
+                (None, '\'' | '\\') => {
+                    quoted.push('\\');
+                    quoted.push(c);
+                }
+                (None, c) => quoted.push(c),
+            }
+        }
+        quoted.push('\'');
+        return quoted;
+    }
+    format!("'{}'", word.replace('\'', "'\\''"))
+}
+
+fn status_result(status: i32) -> Result<(), ShellError> {
+    if status == 0 {
+        Ok(())
+    } else {
+        Err(ShellError::ExitStatus(status))
+    }
+}
+
+fn expand_tilde_word(word: &str) -> String {
+    if word == "~" {
+        return std::env::var("HOME").unwrap_or_else(|_| String::from("~"));
+    }
+    if let Some(rest) = word.strip_prefix("~/")
+        && let Ok(home) = std::env::var("HOME")
+    {
+        return format!("{}/{}", home, rest);
+    }
+    word.to_string()
 }
@@ -0,0 +1,122 @@
+use std::io;
+use std::sync::atomic::{ AtomicBool, Ordering };
+
+// Signal handlers only record that a signal arrived. The shell looks at the
+// flags between commands and while it waits for input, and runs the trap
+// actions from there, where it is safe to do anything. Builtins that can run
+// for long check `interrupted` and stop early so the trap is not held up.
+
+/// What a trap is attached to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TrapCondition {
+    /// Runs when the shell (or a subshell) exits.
+    Exit,
+    Signal(i32),
+    /// Runs after a command fails.
+    Err,
+}
+
+/// Signals that can be trapped, in the order `trap -l` lists them.
+pub const SIGNALS: &[(&str, i32)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("TERM", libc::SIGTERM),
+];
+
+/// Signals that end the shell unless trapped. An EXIT trap still has to run
+/// when one of them arrives.
+pub const FATAL_SIGNALS: &[i32] = &[libc::SIGHUP, libc::SIGINT, libc::SIGTERM];
+
+impl TrapCondition {
+    /// Accepts `INT`, `SIGINT`, `int`, `2`, and `0`/`EXIT`/`ERR`.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let upper = spec.to_ascii_uppercase();
+        let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+        match name {
+            "EXIT" | "0" => return Some(TrapCondition::Exit),
+            "ERR" => return Some(TrapCondition::Err),
+            _ => {}
+        }
+        if let Ok(number) = name.parse::<i32>() {
+            return SIGNALS.iter().find(|(_, n)| *n == number).map(|(_, n)| TrapCondition::Signal(*n));
+        }
+        SIGNALS.iter().find(|(n, _)| *n == name).map(|(_, n)| TrapCondition::Signal(*n))
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            TrapCondition::Exit => "EXIT".to_string(),
+            TrapCondition::Err => "ERR".to_string(),
+            TrapCondition::Signal(number) => SIGNALS
+                .iter()
+                .find(|(_, n)| n == number)
+                .map(|(name, _)| format!("SIG{}", name))
+                .unwrap_or_else(|| number.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Disposition {
+    Default,
+    Ignore,
+    Catch,
+}
+
+const MAX_SIGNAL: usize = 65;
+
+static PENDING: [AtomicBool; MAX_SIGNAL] = [const { AtomicBool::new(false) }; MAX_SIGNAL];
+
+extern "C" fn record_signal(signal: libc::c_int) {
+    if let Some(flag) = PENDING.get(signal as usize) {
+        flag.store(true, Ordering::SeqCst);
+    }
+}
+
+pub fn set_disposition(signal: i32, disposition: Disposition) {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = match disposition {
+            Disposition::Default => libc::SIG_DFL,
+            Disposition::Ignore => libc::SIG_IGN,
+            Disposition::Catch => record_signal as *const () as libc::sighandler_t,
+        };
+        // No SA_RESTART: a read blocked waiting for input has to return, so
+        // that the trap runs then and not after the next line.
+        action.sa_flags = 0;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(signal, &action, std::ptr::null_mut());
+    }
+}
+
+/// True while a caught signal is waiting for its trap to run.
+pub fn interrupted() -> bool {
+    SIGNALS.iter().any(|(_, number)| PENDING[*number as usize].load(Ordering::SeqCst))
+}
+
+/// `interrupted` as an error, for builtins to stop with.
+pub fn check_interrupted() -> io::Result<()> {
+    if interrupted() { Err(io::Error::from(io::ErrorKind::Interrupted)) } else { Ok(()) }
+}
+
+/// Returns the signals that arrived since the last call, lowest first.
+pub fn take_pending() -> Vec<i32> {
+    SIGNALS
+        .iter()
+        .map(|(_, number)| *number)
+        .filter(|number| PENDING[*number as usize].swap(false, Ordering::SeqCst))
+        .collect()
+}
+
+/// Dies from `signal` the way an untrapped signal would have killed us, so
+/// the parent sees the right wait status.
+pub fn terminate_with(signal: i32) -> ! {
+    set_disposition(signal, Disposition::Default);
+    unsafe {
+        libc::raise(signal);
+    }
+    std::process::exit(128 + signal);
+}